@@ -0,0 +1,94 @@
+//! DISCOVER parsing throughput and heap allocations, owned `Message` against
+//! borrowed `MessageRef`.
+//!
+//!     cargo bench -p dhcp-proto
+//!
+//! The packet is the one dhcpcd sends, without padding after the end option
+//! since the server slices its receive buffer to the datagram length.
+//! `Message::deserialize` is what the server used before `MessageRef`, and
+//! `MessageRef::new then to_message` what it does now for the messages it
+//! answers.
+
+use dhcp_proto::options::Option;
+use dhcp_proto::{Message, MessageRef, MessageType};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 1_000_000;
+
+/// System allocator counting the allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn discover() -> Vec<u8> {
+    let mut packet = vec![0u8; 236];
+    packet[..4].copy_from_slice(&[1, 1, 6, 0]);
+    packet[4..8].copy_from_slice(&0x3903_f326u32.to_be_bytes());
+    packet[28..34].copy_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let options: &[u8] = &[
+        99, 130, 83, 99, // magic cookie
+        53, 1, 1, // DHCPDISCOVER
+        61, 7, 1, 0x52, 0x54, 0x00, 0x12, 0x34, 0x56, // client identifier
+        57, 2, 0x05, 0xdc, // maximum message size
+        60, 12, b'd', b'h', b'c', b'p', b'c', b'd', b'-', b'9', b'.', b'4', b'.', b'1',
+        12, 6, b'h', b'o', b's', b't', b'-', b'1', // hostname
+        55, 11, 1, 121, 33, 3, 6, 15, 28, 51, 54, 58, 59, // parameter request list
+        255,
+    ];
+    packet.extend_from_slice(options);
+    packet
+}
+
+fn run(name: &str, mut parse: impl FnMut() -> bool) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(black_box(parse()));
+    }
+    let elapsed: Duration = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<40} {:>8.1} ns/message {:>12.0} messages/s {:>6.1} allocations/message",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS),
+        f64::from(ITERATIONS) / elapsed.as_secs_f64(),
+        allocations as f64 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    let packet = discover();
+    run("Message::deserialize", || {
+        let msg = Message::deserialize(black_box(&packet)).unwrap();
+        msg.message_type() == Some(MessageType::DHCPDISCOVER)
+    });
+    run("MessageRef::new, type and client id", || {
+        let msg = MessageRef::new(black_box(&packet)).unwrap();
+        msg.message_type() == Some(MessageType::DHCPDISCOVER)
+            && msg.option(Option::ClientIdentifier).is_some()
+    });
+    run("MessageRef::new then to_message", || {
+        let msg = MessageRef::new(black_box(&packet)).unwrap().to_message();
+        msg.message_type() == Some(MessageType::DHCPDISCOVER)
+    });
+}
@@ -0,0 +1,429 @@
+//! DHCP wire format (RFC 2131 and RFC 2132), shared by the rdhcp server and
+//! the tools around it.
+//!
+//! `Message::deserialize` parses a packet from a byte slice without copying
+//! it first and never panics on malformed input, `Message::serialize` gives
+//! the bytes back. `MessageRef` reads a packet in place, for the hot paths
+//! which only look at a few fields. Replies are built with `Message::reply_to` and the typed
+//! option setters.
+//!
+//! The crate is `no_std` with `alloc` when the default "std" feature is
+//! disabled.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod options;
+mod view;
+
+pub use view::{MessageRef, OptionRef, Options};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv4Addr;
+use core::time::Duration;
+use options::*;
+
+/// Fixed fields, before the magic cookie
+pub const HEADER_LENGTH: usize = 236;
+/// Fixed fields and magic cookie, the shortest valid message
+pub const MINIMUM_LENGTH: usize = HEADER_LENGTH + 4;
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Why a packet is not a DHCP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than the fixed fields and the magic cookie
+    TooShort(usize),
+    InvalidMagicCookie([u8; 4]),
+    /// The option with this code runs past the end of the packet
+    TruncatedOption(u8),
+    UnknownMessageType(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooShort(length) => write!(
+                f,
+                "{} bytes is too short for a DHCP message, at least {} expected",
+                length, MINIMUM_LENGTH
+            ),
+            Error::InvalidMagicCookie(cookie) => write!(f, "invalid magic cookie {:X?}", cookie),
+            Error::TruncatedOption(code) => write!(f, "option {} is truncated", code),
+            Error::UnknownMessageType(value) => write!(f, "unknown message type {}", value),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/**
+* Format of a dhcp message
+* 0                   1                   2                   3
+  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+  +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+  |     op (1)    |   htype (1)   |   hlen (1)    |   hops (1)    |
+  +---------------+---------------+---------------+---------------+
+  |                            xid (4)                            |
+  +-------------------------------+-------------------------------+
+  |           secs (2)            |           flags (2)           |
+  +-------------------------------+-------------------------------+
+  |                          ciaddr  (4)                          |
+  +---------------------------------------------------------------+
+  |                          yiaddr  (4)                          |
+  +---------------------------------------------------------------+
+  |                          siaddr  (4)                          |
+  +---------------------------------------------------------------+
+  |                          giaddr  (4)                          |
+  +---------------------------------------------------------------+
+  |                                                               |
+  |                          chaddr  (16)                         |
+  |                                                               |
+  |                                                               |
+  +---------------------------------------------------------------+
+  |                                                               |
+  |                          sname   (64)                         |
+  +---------------------------------------------------------------+
+  |                                                               |
+  |                          file    (128)                        |
+  +---------------------------------------------------------------+
+  |                                                               |
+  |                          options (variable)                   |
+  +---------------------------------------------------------------+
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    // 1 = BOOTREQUEST, 2 = BOOTREPLY
+    ///  Message op code / message type.
+    pub op: u8,
+
+    /// Hardware address type, see ARP section in "Assigned Numbers" RFC; e.g., '1' = 10mb ethernet.
+    pub htype: u8,
+
+    /// Hardware address length (e.g.  '6' for 10mb ethernet).
+    pub hlen: u8,
+
+    /// Client sets to zero, optionally used by relay agents when booting via a relay agent.
+    pub hops: u8,
+
+    /// Transaction ID, a random number chosen by the client, used by the client and server to associate messages and responses between a client and a server.
+    pub xid: u32,
+
+    /// Filled in by client, seconds elapsed since client began address acquisition or renewal process.
+    pub secs: u16,
+
+    /// Flags
+    pub flags: u16,
+
+    /// Client IP address; only filled in if client is in BOUND, RENEW or REBINDING state and can respond to ARP requests.
+    pub ciaddr: Ipv4Addr,
+
+    /// 'your' (client) IP address.
+    pub yiaddr: Ipv4Addr,
+
+    /// IP address of next server to use in bootstrap; returned in DHCPOFFER, DHCPACK by server.
+    pub siaddr: Ipv4Addr,
+
+    /// Relay agent IP address, used in booting via a relay agent.
+    pub giaddr: Ipv4Addr,
+
+    /// Client hardware address.
+    pub chaddr: [u8; 16],
+
+    /// Optional server host name, null terminated string.
+    pub sname: [u8; 64],
+
+    /// Boot file name, null terminated string; "generic" name or null in DHCPDISCOVER, fully qualified directory-path name in DHCPOFFER.
+    pub file: [u8; 128],
+
+    /// Optional parameters field.  See the options documents for a list of defined options.
+    pub options: OptionField,
+}
+
+#[repr(u8)]
+pub enum OpCode {
+    BOOTREQUEST = 1,
+    BOOTREPLY = 2,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageType {
+    // Client broadcast to locate available servers.
+    DHCPDISCOVER = 1,
+
+    // Server to client in response to DHCPDISCOVER with
+    // offer of configuration parameters
+    DHCPOFFER = 2,
+
+    // Client message to servers either (a) requesting
+    // offered parameters from one server and implicitly
+    // declining offers from all others, (b) confirming
+    // correctness of previously allocated address after,
+    // e.g., system reboot, or (c) extending the lease on a
+    // particular network address.
+    DHCPREQUEST = 3,
+
+    // Client to server indicating network address is already
+    // in use.
+    DHCPDECLINE = 4,
+
+    // Server to client with configuration parameters,
+    // including committed network address.
+    DHCPACK = 5,
+
+    // Server to client indicating client's notion of network
+    // address is incorrect (e.g., client has moved to new
+    // subnet) or client's lease as expired
+    DHCPNAK = 6,
+
+    // Client to server relinquishing network address and
+    // cancelling remaining lease.
+    DHCPRELEASE = 7,
+
+    // Client to server, asking only for local configuration
+    // parameters; client already has externally configured
+    // network address.
+    DHCPINFORM = 8,
+}
+impl TryFrom<u8> for MessageType {
+    type Error = Error;
+    fn try_from(value: u8) -> Result<Self, Error> {
+        let message_type = match value {
+            1 => MessageType::DHCPDISCOVER,
+            2 => MessageType::DHCPOFFER,
+            3 => MessageType::DHCPREQUEST,
+            4 => MessageType::DHCPDECLINE,
+            5 => MessageType::DHCPACK,
+            6 => MessageType::DHCPNAK,
+            7 => MessageType::DHCPRELEASE,
+            8 => MessageType::DHCPINFORM,
+            _ => return Err(Error::UnknownMessageType(value)),
+        };
+        Ok(message_type)
+    }
+}
+
+
+impl Message {
+    /**
+     * Reply of the server to a client message, without any option but the
+     * message type. The fields are filled as in RFC 2131 table 3 :
+     *
+     * Field      DHCPOFFER            DHCPACK                   DHCPNAK
+     * -----      ---------            -------                   -------
+     * 'op'       BOOTREPLY            BOOTREPLY                 BOOTREPLY
+     * 'htype'    from the client      from the client           from the client
+     * 'hlen'     from the client      from the client           from the client
+     * 'hops'     0                    0                         0
+     * 'xid'      from the client      from the client           from the client
+     * 'secs'     0                    0                         0
+     * 'ciaddr'   0                    'ciaddr' from the client  0
+     * 'yiaddr'   set by the caller    set by the caller         0
+     * 'siaddr'   0, or next server    0, or next server         0
+     * 'flags'    from the client      from the client           from the client
+     * 'giaddr'   from the client      from the client           from the client
+     * 'chaddr'   from the client      from the client           from the client
+     * 'sname'    0, or server name    0, or server name         0
+     * 'file'     0, or boot file      0, or boot file           0
+     */
+    pub fn reply_to(request: &Message, message_type: MessageType) -> Message {
+        let ciaddr = match message_type {
+            MessageType::DHCPACK => request.ciaddr,
+            _ => Ipv4Addr::UNSPECIFIED,
+        };
+        Message {
+            op: OpCode::BOOTREPLY as u8,
+            htype: request.htype,
+            hlen: request.hlen,
+            hops: 0,
+            xid: request.xid,
+            secs: 0,
+            flags: request.flags,
+            ciaddr,
+            yiaddr: Ipv4Addr::UNSPECIFIED,
+            siaddr: Ipv4Addr::UNSPECIFIED,
+            giaddr: request.giaddr,
+            chaddr: request.chaddr,
+            sname: [0u8; 64],
+            file: [0u8; 128],
+            options: OptionField::new(vec![OptionSubfield::new(
+                Option::DHCPMessageType,
+                vec![message_type as u8],
+            )
+            .expect("message type has a length of 1")]),
+        }
+    }
+    /// Length of the serialized message
+    pub fn length(&self) -> usize {
+        // options, then the end option
+        MINIMUM_LENGTH
+            + self
+                .options
+                .options
+                .iter()
+                .map(|option| 2 + option.data.len())
+                .sum::<usize>()
+            + 1
+    }
+    /// Remove the last options until the serialized message is at most
+    /// `max_length` bytes long, the removed options are returned
+    pub fn truncate(&mut self, max_length: usize) -> Vec<OptionSubfield> {
+        let mut removed: Vec<OptionSubfield> = Vec::new();
+        while self.length() > max_length {
+            match self.options.options.pop() {
+                Some(option) => removed.push(option),
+                None => break,
+            }
+        }
+        removed.reverse();
+        removed
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let xid_bytes = self.xid.to_be_bytes();
+        let secs_bytes = self.secs.to_be_bytes();
+        let flags_bytes = self.flags.to_be_bytes();
+        let ciaddr_bytes = self.ciaddr.octets().to_vec();
+        let yiaddr_bytes = self.yiaddr.octets().to_vec();
+        let siaddr_bytes = self.siaddr.octets().to_vec();
+        let giaddr_bytes = self.giaddr.octets().to_vec();
+
+        let mut res: Vec<u8> = vec![self.op, self.htype, self.hlen, self.hops];
+
+        res.extend_from_slice(&xid_bytes);
+        res.extend_from_slice(&secs_bytes);
+        res.extend_from_slice(&flags_bytes);
+        res.extend_from_slice(&ciaddr_bytes);
+        res.extend_from_slice(&yiaddr_bytes);
+        res.extend_from_slice(&siaddr_bytes);
+        res.extend_from_slice(&giaddr_bytes);
+        res.extend_from_slice(&self.chaddr);
+        res.extend_from_slice(&self.sname);
+        res.extend_from_slice(&self.file);
+        res.extend(&self.options.to_bytes());
+
+        res
+    }
+    /// Parse a message, the options end at the end option or at the end of
+    /// the buffer
+    pub fn deserialize(buffer: &[u8]) -> Result<Message, Error> {
+        if buffer.len() < MINIMUM_LENGTH {
+            return Err(Error::TooShort(buffer.len()));
+        }
+        let u16_at = |offset: usize| u16::from_be_bytes([buffer[offset], buffer[offset + 1]]);
+        let addr_at = |offset: usize| {
+            Ipv4Addr::new(
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            )
+        };
+        let mut chaddr = [0u8; 16];
+        chaddr.copy_from_slice(&buffer[28..44]);
+        let mut sname = [0u8; 64];
+        sname.copy_from_slice(&buffer[44..108]);
+        let mut file = [0u8; 128];
+        file.copy_from_slice(&buffer[108..HEADER_LENGTH]);
+        Ok(Message {
+            op: buffer[0],
+            htype: buffer[1],
+            hlen: buffer[2],
+            hops: buffer[3],
+            xid: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
+            secs: u16_at(8),
+            flags: u16_at(10),
+            ciaddr: addr_at(12),
+            yiaddr: addr_at(16),
+            siaddr: addr_at(20),
+            giaddr: addr_at(24),
+            chaddr,
+            sname,
+            file,
+            options: OptionField::from_bytes(&buffer[HEADER_LENGTH..])?,
+        })
+    }
+    /// Type of the message, option 53
+    pub fn message_type(&self) -> core::option::Option<MessageType> {
+        let op = self.option(Option::DHCPMessageType)?;
+        MessageType::try_from(*op.data.first()?).ok()
+    }
+    pub fn add_options(mut self, option: OptionSubfield) -> Self {
+        self.options.options.push(option);
+        self
+    }
+    pub fn extend_options(mut self, options: impl IntoIterator<Item = OptionSubfield>) -> Self {
+        self.options.options.extend(options);
+        self
+    }
+    /// Add an option, replacing the one with the same code
+    pub fn set_option(mut self, option: OptionSubfield) -> Self {
+        self.options.options.retain(|e| e.op_code != option.op_code);
+        self.options.options.push(option);
+        self
+    }
+    pub fn set_yiaddr(mut self, yiaddr: Ipv4Addr) -> Self {
+        self.yiaddr = yiaddr;
+        self
+    }
+    pub fn set_server_identifier(self, addr: Ipv4Addr) -> Self {
+        self.set_address(Option::ServerIdentifier, addr)
+    }
+    pub fn set_lease_time(self, lease: Duration) -> Self {
+        self.set_seconds(Option::IPAddressLeaseTime, lease)
+    }
+    /// T1, option 58
+    pub fn set_renewal_time(self, duration: Duration) -> Self {
+        self.set_seconds(Option::RenewalTimeValue, duration)
+    }
+    /// T2, option 59
+    pub fn set_rebinding_time(self, duration: Duration) -> Self {
+        self.set_seconds(Option::RebindingTimeValue, duration)
+    }
+    /// Error message of a DHCPNAK, option 56
+    pub fn set_message(self, message: &str) -> Self {
+        match OptionSubfield::new(Option::Message, message.as_bytes().to_vec()) {
+            Ok(option) => self.set_option(option),
+            Err(_) => self,
+        }
+    }
+    fn set_address(self, op: Option, addr: Ipv4Addr) -> Self {
+        let option = OptionSubfield::new(op, addr.octets().to_vec())
+            .expect("address options have a length of 4");
+        self.set_option(option)
+    }
+    /// Durations are sent in seconds, saturating at u32::MAX
+    fn set_seconds(self, op: Option, duration: Duration) -> Self {
+        let seconds = u32::try_from(duration.as_secs()).unwrap_or(u32::MAX);
+        let option = OptionSubfield::new(op, seconds.to_be_bytes().to_vec())
+            .expect("time options have a length of 4");
+        self.set_option(option)
+    }
+    pub fn option(&self, code: Option) -> core::option::Option<&OptionSubfield> {
+        let code = code as u8;
+        self.options.options.iter().find(|e| e.op_code == code)
+    }
+    /// Client hardware address formatted as "aa:bb:cc:dd:ee:ff"
+    pub fn mac_address(&self) -> String {
+        let len = usize::from(self.hlen).min(self.chaddr.len());
+        format_mac(&self.chaddr[..len])
+    }
+}
+
+pub(crate) fn format_mac(address: &[u8]) -> String {
+    address
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "op = {:X}\thtype = {:X}\thlen = {:X}\thops = {:X}\txid = {:X}\tsecs = {:X}\tflags = {:X}\tciaddr = {}\tyiaddr = {}\tsiaddr = {}\tgiaddr = {}\tchaddr = {:X?}\tsname = {:X?}\tfile = {:X?}\t options = {}", self.op, self.htype, self.hlen, self.hops, self.xid, self.secs, self.flags, self.ciaddr, self.yiaddr, self.siaddr, self.giaddr, self.chaddr, self.sname, self.file, self.options)
+    }
+}
@@ -1,18 +1,24 @@
-use std::fmt;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
 
-#[derive(Debug, Clone)]
+use crate::{Error, MAGIC_COOKIE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OptionField {
     pub magic_cookies: [u8; 4],
     pub options: Vec<OptionSubfield>,
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OptionSubfield {
     pub op_code: u8,
     pub op_len: u8,
     pub data: Vec<u8>,
 }
 #[repr(u8)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Option {
     Pad = 0,
     End = 255,
@@ -77,10 +83,77 @@ pub enum Option {
     RebindingTimeValue = 59,
     ClassIdentifier = 60,
     ClientIdentifier = 61,
+    TFTPServerName = 66,
+    BootfileName = 67,
+    UserClass = 77,             // RFC 3004
+    ClientFQDN = 81,            // RFC 4702
+    RelayAgentInformation = 82, // RFC 3046
+    ClientSystemArchitecture = 93, // RFC 4578
     // I know there is other option but it will
     // be fine for the moment
 }
 
+impl Option {
+    /// Name used in the configuration file, the same as dhcpd.conf(5)
+    pub fn from_name(name: &str) -> core::option::Option<Option> {
+        let op = match name {
+            "subnet-mask" => Option::SubnetMask,
+            "time-offset" => Option::TimeOffset,
+            "routers" => Option::Router,
+            "time-servers" => Option::TimeServer,
+            "ien116-name-servers" => Option::NameServer,
+            "domain-name-servers" => Option::DomainNameServer,
+            "log-servers" => Option::LogServer,
+            "cookie-servers" => Option::CookieServer,
+            "lpr-servers" => Option::LPR,
+            "impress-servers" => Option::ImpressServer,
+            "resource-location-servers" => Option::ResourceLocationServer,
+            "host-name" => Option::HostName,
+            "boot-size" => Option::BootFileSize,
+            "merit-dump" => Option::MeritDumpFile,
+            "domain-name" => Option::DomainName,
+            "swap-server" => Option::SwapServer,
+            "root-path" => Option::RootPath,
+            "extensions-path" => Option::ExtensionsPath,
+            "ip-forwarding" => Option::IPForwarding,
+            "non-local-source-routing" => Option::NonLocalSourceRouting,
+            "policy-filter" => Option::PolicyFilter,
+            "max-dgram-reassembly" => Option::MaximumDatagramReassemblySize,
+            "default-ip-ttl" => Option::DefaultIpTTL,
+            "path-mtu-aging-timeout" => Option::PathMTUAgingTimeout,
+            "path-mtu-plateau-table" => Option::PathMTUPlateauTable,
+            "interface-mtu" => Option::InterfaceMTU,
+            "all-subnets-local" => Option::AllSubnetsAreLocal,
+            "broadcast-address" => Option::BroadcastAddress,
+            "perform-mask-discovery" => Option::PerformMaskDiscovery,
+            "mask-supplier" => Option::MaskSupplier,
+            "router-discovery" => Option::PerformRouterDiscovery,
+            "router-solicitation-address" => Option::RouterSolicitationAddress,
+            "static-routes" => Option::StaticRoute,
+            "trailer-encapsulation" => Option::TrailerEncapsulation,
+            "arp-cache-timeout" => Option::ARPCacheTimeout,
+            "ieee802-3-encapsulation" => Option::EthernetEncapsulation,
+            "default-tcp-ttl" => Option::TCPDefaultTTL,
+            "tcp-keepalive-interval" => Option::TCPKeepaliveInterval,
+            "tcp-keepalive-garbage" => Option::TCPKeepaliveGarbage,
+            "nis-domain" => Option::NetworkInformationServiceDomain,
+            "nis-servers" => Option::NetworkInformationServers,
+            "ntp-servers" => Option::NetworkTimeProtocolServers,
+            "vendor-encapsulated-options" => Option::VendorSpecificInformation,
+            "netbios-name-servers" => Option::NetBIOSOverTCPIPNameServer,
+            "netbios-dd-server" => Option::NetBIOSOverTCPIPDatagramDistributionServer,
+            "netbios-node-type" => Option::NetBIOSOverTCPIPNodeType,
+            "netbios-scope" => Option::NetBIOSOverTCPIPScope,
+            "font-servers" => Option::XWindowSystemFontServer,
+            "x-display-manager" => Option::XWindowSystemDisplayManager,
+            "tftp-server-name" => Option::TFTPServerName,
+            "bootfile-name" => Option::BootfileName,
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
 impl OptionSubfield {
     pub fn new(op: Option, mut data: Vec<u8>) -> Result<OptionSubfield, String> {
         let op_len: u8;
@@ -122,7 +195,7 @@ impl OptionSubfield {
             | Option::NetworkTimeProtocolServers
             | Option::XWindowSystemFontServer
             | Option::XWindowSystemDisplayManager => {
-                op_len = u8::try_from(data.len()).expect("data is out of bound");
+                op_len = u8::try_from(data.len()).map_err(|_| format!("{:?} data is longer than 255 bytes", op))?;
                 if op_len / 4 != 0 && op_len < 4 {
                     return Err(format!(
                         "{:?} must have a data length of a multiple of 4",
@@ -144,8 +217,13 @@ impl OptionSubfield {
             | Option::NetBIOSOverTCPIPScope
             | Option::ParameterRequestList
             | Option::Message
-            | Option::ClassIdentifier => {
-                op_len = u8::try_from(data.len()).expect("data is out of bound");
+            | Option::ClassIdentifier
+            | Option::TFTPServerName
+            | Option::BootfileName
+            | Option::UserClass
+            | Option::ClientFQDN
+            | Option::RelayAgentInformation => {
+                op_len = u8::try_from(data.len()).map_err(|_| format!("{:?} data is longer than 255 bytes", op))?;
                 if op_len < 1 {
                     return Err(format!("{:?} can't have empty data", op));
                 }
@@ -175,7 +253,7 @@ impl OptionSubfield {
                 }
             }
             Option::PolicyFilter | Option::StaticRoute => {
-                op_len = u8::try_from(data.len()).expect("data is out of bound");
+                op_len = u8::try_from(data.len()).map_err(|_| format!("{:?} data is longer than 255 bytes", op))?;
                 if op_len / 8 != 0 && op_len < 8 {
                     return Err(format!(
                         "{:?} must have a data length of a multiple of 8",
@@ -183,8 +261,8 @@ impl OptionSubfield {
                     ));
                 }
             }
-            Option::PathMTUPlateauTable => {
-                op_len = u8::try_from(data.len()).expect("data is out of bound");
+            Option::PathMTUPlateauTable | Option::ClientSystemArchitecture => {
+                op_len = u8::try_from(data.len()).map_err(|_| format!("{:?} data is longer than 255 bytes", op))?;
                 if op_len / 2 != 0 && op_len < 2 {
                     return Err(format!(
                         "{:?} must have a data length of a multiple of 2",
@@ -201,12 +279,22 @@ impl OptionSubfield {
             data,
         })
     }
-    pub fn from_bytes(input: Vec<u8>) -> OptionSubfield {
-        OptionSubfield {
-            op_code: input[0],
-            op_len: input[1],
-            data: input[2..input.len()].to_vec(),
-        }
+    /// Parse one option, the input starting at its code. Bytes after the
+    /// option are ignored.
+    pub fn from_bytes(input: &[u8]) -> Result<OptionSubfield, Error> {
+        let (op_code, op_len) = match input {
+            [op_code, op_len, ..] => (*op_code, *op_len),
+            [op_code] => return Err(Error::TruncatedOption(*op_code)),
+            [] => return Err(Error::TruncatedOption(0)),
+        };
+        let data = input
+            .get(2..2 + usize::from(op_len))
+            .ok_or(Error::TruncatedOption(op_code))?;
+        Ok(OptionSubfield {
+            op_code,
+            op_len,
+            data: data.to_vec(),
+        })
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![self.op_code, self.op_len];
@@ -217,40 +305,38 @@ impl OptionSubfield {
 impl OptionField {
     pub fn new(options: Vec<OptionSubfield>) -> OptionField {
         OptionField {
-            magic_cookies: [99, 130, 83, 99], // set by rfc 1497
+            magic_cookies: MAGIC_COOKIE, // set by rfc 1497
             options,
         }
     }
-    pub fn from_bytes(input: Vec<u8>) -> OptionField {
-        let magic_cookies: [u8; 4] = input[0..=3]
-            .try_into()
-            .expect("slice with incorrect length");
+    /// Parse the options part of a message, from the magic cookie up to the
+    /// end option or the end of the input
+    pub fn from_bytes(input: &[u8]) -> Result<OptionField, Error> {
+        let magic_cookies: [u8; 4] = match input {
+            [a, b, c, d, ..] => [*a, *b, *c, *d],
+            _ => return Err(Error::TooShort(crate::HEADER_LENGTH + input.len())),
+        };
+        if magic_cookies != MAGIC_COOKIE {
+            return Err(Error::InvalidMagicCookie(magic_cookies));
+        }
         let mut offset = 4;
         let mut options: Vec<OptionSubfield> = Vec::new();
-
-        while offset < input.len() - 1 {
-            match input[offset] {
-                255 => {
-                    break;
-                }
-                0 => {
-                    // pad
-                    offset += 1;
-                }
+        while let Some(&code) = input.get(offset) {
+            match code {
+                255 => break,
+                // pad
+                0 => offset += 1,
                 _ => {
-                    let length: usize = input[offset + 1].into();
-                    options.push(OptionSubfield::from_bytes(
-                        input[offset..=offset + 1 + length].to_vec(),
-                    ));
-                    offset += length + 2;
+                    let option = OptionSubfield::from_bytes(&input[offset..])?;
+                    offset += 2 + option.data.len();
+                    options.push(option);
                 }
             }
         }
-
-        OptionField {
+        Ok(OptionField {
             magic_cookies,
             options,
-        }
+        })
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = self.magic_cookies.to_vec();
@@ -274,10 +360,10 @@ impl fmt::Display for OptionSubfield {
 
 impl fmt::Display for OptionField {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut res: String = String::from(format!(
+        let mut res: String = format!(
             "{{ magic_cookies : {:X?} subfield: [ ",
             self.magic_cookies
-        ));
+        );
         for op in self.options.iter() {
             res = res + &format!("{{ {} }},", op);
         }
@@ -0,0 +1,173 @@
+use alloc::string::String;
+use core::net::Ipv4Addr;
+
+use crate::options::Option;
+use crate::{format_mac, Error, Message, MessageType, HEADER_LENGTH, MAGIC_COOKIE, MINIMUM_LENGTH};
+
+/**
+ * Message read in place from a received packet.
+ *
+ * The packet is checked once by `MessageRef::new` : the fixed fields, the
+ * magic cookie, and every option fitting in the packet. The fields are then
+ * read on demand and the options are walked lazily without allocating, so
+ * the packets ignored early (wrong type, other server of a load balancing
+ * pair, ...) cost almost nothing. `to_message` gives the owned `Message`
+ * for the ones which are handled.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct MessageRef<'a> {
+    buffer: &'a [u8],
+    /// End of the options, at the end option or the end of the packet
+    options_end: usize,
+}
+
+/// Option borrowed from a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionRef<'a> {
+    pub code: u8,
+    pub data: &'a [u8],
+}
+
+/// Options of a `MessageRef`, in packet order, pads skipped
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<MessageRef<'a>, Error> {
+        if buffer.len() < MINIMUM_LENGTH {
+            return Err(Error::TooShort(buffer.len()));
+        }
+        let cookie = [
+            buffer[HEADER_LENGTH],
+            buffer[HEADER_LENGTH + 1],
+            buffer[HEADER_LENGTH + 2],
+            buffer[HEADER_LENGTH + 3],
+        ];
+        if cookie != MAGIC_COOKIE {
+            return Err(Error::InvalidMagicCookie(cookie));
+        }
+        let mut offset = MINIMUM_LENGTH;
+        while let Some(&code) = buffer.get(offset) {
+            match code {
+                255 => break,
+                0 => offset += 1,
+                _ => {
+                    let length = buffer.get(offset + 1).ok_or(Error::TruncatedOption(code))?;
+                    offset += 2 + usize::from(*length);
+                    if offset > buffer.len() {
+                        return Err(Error::TruncatedOption(code));
+                    }
+                }
+            }
+        }
+        Ok(MessageRef {
+            buffer,
+            options_end: offset.min(buffer.len()),
+        })
+    }
+    pub fn op(&self) -> u8 {
+        self.buffer[0]
+    }
+    pub fn htype(&self) -> u8 {
+        self.buffer[1]
+    }
+    pub fn hlen(&self) -> u8 {
+        self.buffer[2]
+    }
+    pub fn hops(&self) -> u8 {
+        self.buffer[3]
+    }
+    pub fn xid(&self) -> u32 {
+        u32::from_be_bytes([self.buffer[4], self.buffer[5], self.buffer[6], self.buffer[7]])
+    }
+    pub fn secs(&self) -> u16 {
+        u16::from_be_bytes([self.buffer[8], self.buffer[9]])
+    }
+    pub fn flags(&self) -> u16 {
+        u16::from_be_bytes([self.buffer[10], self.buffer[11]])
+    }
+    pub fn ciaddr(&self) -> Ipv4Addr {
+        self.addr_at(12)
+    }
+    pub fn yiaddr(&self) -> Ipv4Addr {
+        self.addr_at(16)
+    }
+    pub fn siaddr(&self) -> Ipv4Addr {
+        self.addr_at(20)
+    }
+    pub fn giaddr(&self) -> Ipv4Addr {
+        self.addr_at(24)
+    }
+    pub fn chaddr(&self) -> &'a [u8] {
+        &self.buffer[28..44]
+    }
+    /// Client hardware address, the first 'hlen' bytes of 'chaddr'
+    pub fn hardware_address(&self) -> &'a [u8] {
+        &self.chaddr()[..usize::from(self.hlen()).min(16)]
+    }
+    /// Client hardware address formatted as "aa:bb:cc:dd:ee:ff"
+    pub fn mac_address(&self) -> String {
+        format_mac(self.hardware_address())
+    }
+    pub fn sname(&self) -> &'a [u8] {
+        &self.buffer[44..108]
+    }
+    pub fn file(&self) -> &'a [u8] {
+        &self.buffer[108..HEADER_LENGTH]
+    }
+    pub fn options(&self) -> Options<'a> {
+        Options {
+            buffer: &self.buffer[..self.options_end],
+            offset: MINIMUM_LENGTH,
+        }
+    }
+    /// Data of the first option with this code
+    pub fn option(&self, code: Option) -> core::option::Option<&'a [u8]> {
+        let code = code as u8;
+        self.options()
+            .find(|option| option.code == code)
+            .map(|option| option.data)
+    }
+    /// Type of the message, option 53
+    pub fn message_type(&self) -> core::option::Option<MessageType> {
+        let data = self.option(Option::DHCPMessageType)?;
+        MessageType::try_from(*data.first()?).ok()
+    }
+    /// The whole packet
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buffer
+    }
+    pub fn to_message(&self) -> Message {
+        Message::deserialize(self.buffer).expect("checked by MessageRef::new")
+    }
+    fn addr_at(&self, offset: usize) -> Ipv4Addr {
+        Ipv4Addr::new(
+            self.buffer[offset],
+            self.buffer[offset + 1],
+            self.buffer[offset + 2],
+            self.buffer[offset + 3],
+        )
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = OptionRef<'a>;
+    fn next(&mut self) -> core::option::Option<OptionRef<'a>> {
+        loop {
+            let code = *self.buffer.get(self.offset)?;
+            match code {
+                255 => return None,
+                0 => self.offset += 1,
+                _ => {
+                    let length = usize::from(*self.buffer.get(self.offset + 1)?);
+                    let data = self.buffer.get(self.offset + 2..self.offset + 2 + length)?;
+                    self.offset += 2 + length;
+                    return Some(OptionRef { code, data });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Serialization round trips of arbitrary messages and options, and parsing
+//! of arbitrary bytes.
+
+use dhcp_proto::options::{OptionField, OptionSubfield};
+use dhcp_proto::{Message, MessageRef, MAGIC_COOKIE};
+use proptest::prelude::*;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+/// Any option but pad and end, which have no length
+fn option() -> impl Strategy<Value = OptionSubfield> {
+    (1u8..=254, prop::collection::vec(any::<u8>(), 0..=255)).prop_map(|(op_code, data)| {
+        OptionSubfield {
+            op_code,
+            op_len: data.len() as u8,
+            data,
+        }
+    })
+}
+
+fn option_field() -> impl Strategy<Value = OptionField> {
+    prop::collection::vec(option(), 0..16).prop_map(OptionField::new)
+}
+
+fn address() -> impl Strategy<Value = Ipv4Addr> {
+    any::<u32>().prop_map(Ipv4Addr::from)
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (
+        (any::<[u8; 4]>(), any::<u32>(), any::<u16>(), any::<u16>()),
+        (address(), address(), address(), address()),
+        (any::<[u8; 16]>(), prop::collection::vec(any::<u8>(), 192)),
+        option_field(),
+    )
+        .prop_map(
+            |(([op, htype, hlen, hops], xid, secs, flags), addresses, (chaddr, names), options)| {
+                let (ciaddr, yiaddr, siaddr, giaddr) = addresses;
+                let mut sname = [0u8; 64];
+                sname.copy_from_slice(&names[..64]);
+                let mut file = [0u8; 128];
+                file.copy_from_slice(&names[64..]);
+                Message {
+                    op,
+                    htype,
+                    hlen,
+                    hops,
+                    xid,
+                    secs,
+                    flags,
+                    ciaddr,
+                    yiaddr,
+                    siaddr,
+                    giaddr,
+                    chaddr,
+                    sname,
+                    file,
+                    options,
+                }
+            },
+        )
+}
+
+/// Packets starting with a valid header and magic cookie, followed by
+/// anything
+fn packet() -> impl Strategy<Value = Vec<u8>> {
+    (
+        prop::collection::vec(any::<u8>(), 236),
+        prop::collection::vec(any::<u8>(), 0..512),
+    )
+        .prop_map(|(mut packet, options)| {
+            packet.extend_from_slice(&MAGIC_COOKIE);
+            packet.extend(options);
+            packet
+        })
+}
+
+proptest! {
+    #[test]
+    fn message_roundtrip(message in message()) {
+        let bytes = message.serialize();
+        prop_assert_eq!(bytes.len(), message.length());
+        prop_assert_eq!(Message::deserialize(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn option_field_roundtrip(field in option_field()) {
+        prop_assert_eq!(OptionField::from_bytes(&field.to_bytes()), Ok(field));
+    }
+
+    #[test]
+    fn option_roundtrip(option in option()) {
+        prop_assert_eq!(OptionSubfield::from_bytes(&option.to_bytes()), Ok(option));
+    }
+
+    #[test]
+    fn view_matches_message(bytes in packet()) {
+        match (Message::deserialize(&bytes), MessageRef::new(&bytes)) {
+            (Ok(message), Ok(view)) => {
+                prop_assert_eq!(view.options().count(), message.options.options.len());
+                prop_assert_eq!(view.to_message(), message);
+            }
+            (Err(e), Err(f)) => prop_assert_eq!(e, f),
+            (message, view) => prop_assert!(false, "{:?} but {:?}", message, view.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn deserialize_any_bytes(bytes in prop::collection::vec(any::<u8>(), 0..600)) {
+        if let Ok(message) = Message::deserialize(&bytes) {
+            prop_assert_eq!(Message::deserialize(&message.serialize()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn truncate_fits(mut message in message(), max_length in 241usize..2048) {
+        let removed = message.truncate(max_length);
+        prop_assert!(message.length() <= max_length);
+        prop_assert!(message.serialize().len() <= max_length);
+        prop_assert!(removed.is_empty() || message.length() + 2 + removed[0].data.len() > max_length);
+    }
+}
+
+/// The fuzzing corpus is made of client packets, they all parse
+#[test]
+fn corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus/deserialize");
+    let mut count = 0;
+    for entry in fs::read_dir(corpus).expect("corpus directory") {
+        let path = entry.expect("corpus entry").path();
+        let bytes = fs::read(&path).expect("corpus packet");
+        let message = Message::deserialize(&bytes)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert!(message.message_type().is_some(), "{}", path.display());
+        assert_eq!(MessageRef::new(&bytes).map(|view| view.to_message()), Ok(message.clone()));
+        assert_eq!(Message::deserialize(&message.serialize()), Ok(message));
+        count += 1;
+    }
+    assert!(count > 0);
+}
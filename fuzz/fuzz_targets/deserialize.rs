@@ -0,0 +1,21 @@
+//! `Message::deserialize` on any packet, checked against `MessageRef`.
+//!
+//! A packet which parses must give back the same message once serialized
+//! and parsed again.
+
+#![no_main]
+
+use dhcp_proto::{Message, MessageRef};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let message = Message::deserialize(data);
+    let view = MessageRef::new(data);
+    assert_eq!(message.is_ok(), view.is_ok());
+    if let (Ok(message), Ok(view)) = (message, view) {
+        assert_eq!(view.to_message(), message);
+        assert_eq!(view.options().count(), message.options.options.len());
+        let reparsed = Message::deserialize(&message.serialize()).expect("serialized message");
+        assert_eq!(reparsed, message);
+    }
+});
@@ -0,0 +1,13 @@
+//! `OptionField::from_bytes` on any options part, from the magic cookie.
+
+#![no_main]
+
+use dhcp_proto::options::OptionField;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(field) = OptionField::from_bytes(data) {
+        let reparsed = OptionField::from_bytes(&field.to_bytes()).expect("serialized options");
+        assert_eq!(reparsed, field);
+    }
+});
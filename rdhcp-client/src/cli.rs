@@ -0,0 +1,75 @@
+use clap::{Parser, ValueEnum};
+use std::net::SocketAddr;
+
+/// DHCP test client and load generator. Every client gets a lease
+/// (DHCPDISCOVER, DHCPOFFER, DHCPREQUEST, DHCPACK) then goes through the
+/// steps, in order.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Steps once the lease is acknowledged
+    #[arg(value_enum)]
+    pub steps: Vec<Step>,
+    /// Server, or relay, the requests are sent to
+    #[arg(short, long, default_value = "127.0.0.1:67")]
+    pub server: SocketAddr,
+    /// Address of the client socket, servers answering on port 68 need
+    /// "0.0.0.0:68"
+    #[arg(short, long, default_value = "0.0.0.0:0")]
+    pub bind: SocketAddr,
+    /// Number of clients, each with its own hardware address
+    #[arg(short, long, default_value_t = 1)]
+    pub clients: usize,
+    /// Clients running at the same time
+    #[arg(short = 'j', long, default_value_t = 64)]
+    pub concurrency: usize,
+    /// Hardware address of the first client, the next ones count from it
+    #[arg(long, default_value = "02:00:00:00:00:01", value_parser = parse_mac)]
+    pub mac: [u8; 6],
+    /// Milliseconds to wait for a reply before sending the request again
+    #[arg(short, long, default_value_t = 1000)]
+    pub timeout: u64,
+    /// Times a request is sent again when there is no reply
+    #[arg(short, long, default_value_t = 2)]
+    pub retries: u32,
+    /// Print the outcome of every client
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// Extend the lease, DHCPREQUEST with 'ciaddr' filled
+    Renew,
+    /// Give the address back, DHCPRELEASE
+    Release,
+    /// Report the address as already in use, DHCPDECLINE
+    Decline,
+}
+
+pub fn handle() -> Args {
+    Args::parse()
+}
+
+/// Hardware address of the client `index`, counted from `first`
+pub fn nth_mac(first: [u8; 6], index: usize) -> [u8; 6] {
+    let mut bytes = [0u8; 8];
+    bytes[2..].copy_from_slice(&first);
+    let mac = u64::from_be_bytes(bytes)
+        .wrapping_add(index as u64)
+        .to_be_bytes();
+    let mut next = [0u8; 6];
+    next.copy_from_slice(&mac[2..]);
+    next
+}
+
+fn parse_mac(value: &str) -> Result<[u8; 6], String> {
+    let bytes: Vec<u8> = value
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid hardware address : {}", e))?;
+    bytes
+        .try_into()
+        .map_err(|_| "a hardware address has 6 bytes".to_string())
+}
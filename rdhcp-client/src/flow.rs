@@ -0,0 +1,307 @@
+use dhcp_proto::options::{Option, OptionField, OptionSubfield};
+use dhcp_proto::{Message, MessageRef, MessageType, OpCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cli::{Args, Step};
+
+// How often the dispatcher checks whether the clients are done
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+// Largest UDP payload over IPv4
+const MAX_UDP_PAYLOAD: usize = 65507;
+// Parameters asked for, like most clients do
+const PARAMETER_REQUEST_LIST: [Option; 6] = [
+    Option::SubnetMask,
+    Option::Router,
+    Option::DomainNameServer,
+    Option::DomainName,
+    Option::IPAddressLeaseTime,
+    Option::ServerIdentifier,
+];
+
+/// Request and reply pairs whose latency is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exchange {
+    Discover,
+    Request,
+    Renew,
+}
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Exchange::Discover => "DISCOVER-OFFER",
+            Exchange::Request => "REQUEST-ACK",
+            Exchange::Renew => "RENEW-ACK",
+        };
+        f.pad(name)
+    }
+}
+
+/// Why a client did not go through all its steps
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    /// Nothing came back after the retries, the reply expected is given
+    NoReply(&'static str),
+    /// DHCPNAK, with its message
+    Nak(String),
+    /// Reply missing something the next request needs
+    InvalidReply(&'static str),
+    Io(String),
+}
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::NoReply(expected) => write!(f, "no {}", expected),
+            Failure::Nak(message) if message.is_empty() => write!(f, "DHCPNAK"),
+            Failure::Nak(message) => write!(f, "DHCPNAK : {}", message),
+            Failure::InvalidReply(reason) => write!(f, "invalid reply : {}", reason),
+            Failure::Io(e) => write!(f, "unable to send : {}", e),
+        }
+    }
+}
+
+/// What happened to one client
+pub struct Outcome {
+    pub mac: String,
+    pub address: std::option::Option<Ipv4Addr>,
+    pub latencies: Vec<(Exchange, Duration)>,
+    pub result: Result<(), Failure>,
+}
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let address = self
+            .address
+            .map_or("-".to_string(), |address| address.to_string());
+        match &self.result {
+            Ok(()) => write!(f, "{} {:<15} ok", self.mac, address),
+            Err(failure) => write!(f, "{} {:<15} {}", self.mac, address, failure),
+        }
+    }
+}
+
+/**
+ * Socket shared by every client. The replies are read by `dispatch` and
+ * handed to the client waiting for their transaction, so thousands of
+ * clients only need one socket and one port.
+ */
+pub struct Exchanger {
+    socket: UdpSocket,
+    server: SocketAddr,
+    timeout: Duration,
+    retries: u32,
+    /// Clients waiting for a reply, by transaction ID
+    pending: Mutex<HashMap<u32, Sender<Message>>>,
+}
+
+impl Exchanger {
+    pub fn new(args: &Args) -> io::Result<Exchanger> {
+        let socket = UdpSocket::bind(args.bind)?;
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        Ok(Exchanger {
+            socket,
+            server: args.server,
+            timeout: Duration::from_millis(args.timeout),
+            retries: args.retries,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+    /// Read the replies until `done` is set
+    pub fn dispatch(&self, done: &AtomicBool) {
+        let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+        while !done.load(Ordering::Relaxed) {
+            let length = match self.socket.recv_from(&mut buffer) {
+                Ok((length, _)) => length,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    eprintln!("ERR: unable to receive the replies : {}", e);
+                    return;
+                }
+            };
+            let reply = match MessageRef::new(&buffer[..length]) {
+                Ok(reply) if reply.op() == OpCode::BOOTREPLY as u8 => reply,
+                _ => continue,
+            };
+            if let Some(client) = self.pending.lock().unwrap().get(&reply.xid()) {
+                let _ = client.send(reply.to_message());
+            }
+        }
+    }
+    /// Send a request until a reply of one of the `expected` types comes
+    /// back. The latency is counted from the first try.
+    fn exchange(
+        &self,
+        request: &Message,
+        expected: &[MessageType],
+    ) -> Result<(Message, Duration), Failure> {
+        let (client, replies) = mpsc::channel();
+        self.pending.lock().unwrap().insert(request.xid, client);
+        let result = self.wait(request, expected, &replies);
+        self.pending.lock().unwrap().remove(&request.xid);
+        result
+    }
+    fn wait(
+        &self,
+        request: &Message,
+        expected: &[MessageType],
+        replies: &Receiver<Message>,
+    ) -> Result<(Message, Duration), Failure> {
+        let started = Instant::now();
+        for _ in 0..=self.retries {
+            self.send(request)?;
+            let deadline = Instant::now() + self.timeout;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                let reply = match replies.recv_timeout(left) {
+                    Ok(reply) => reply,
+                    Err(_) => break,
+                };
+                let wanted = reply
+                    .message_type()
+                    .is_some_and(|message_type| expected.contains(&message_type));
+                if wanted && reply.chaddr == request.chaddr {
+                    return Ok((reply, started.elapsed()));
+                }
+            }
+        }
+        let expected = match expected.first() {
+            Some(MessageType::DHCPOFFER) => "DHCPOFFER",
+            _ => "DHCPACK",
+        };
+        Err(Failure::NoReply(expected))
+    }
+    /// Send a request without waiting for a reply
+    fn send(&self, request: &Message) -> Result<(), Failure> {
+        self.socket
+            .send_to(&request.serialize(), self.server)
+            .map(|_| ())
+            .map_err(|e| Failure::Io(e.to_string()))
+    }
+}
+
+/// Run a client : get a lease then go through the steps
+pub fn run(exchanger: &Exchanger, mac: [u8; 6], steps: &[Step]) -> Outcome {
+    let mut outcome = Outcome {
+        mac: request(MessageType::DHCPDISCOVER, mac).mac_address(),
+        address: None,
+        latencies: Vec::new(),
+        result: Ok(()),
+    };
+    outcome.result = lease(exchanger, mac, steps, &mut outcome);
+    outcome
+}
+
+fn lease(
+    exchanger: &Exchanger,
+    mac: [u8; 6],
+    steps: &[Step],
+    outcome: &mut Outcome,
+) -> Result<(), Failure> {
+    let discover = with_parameters(request(MessageType::DHCPDISCOVER, mac));
+    let (offer, latency) = exchanger.exchange(&discover, &[MessageType::DHCPOFFER])?;
+    outcome.latencies.push((Exchange::Discover, latency));
+    let server_identifier = offer
+        .option(Option::ServerIdentifier)
+        .ok_or(Failure::InvalidReply("DHCPOFFER without server identifier"))?
+        .clone();
+
+    // Same transaction as the DHCPDISCOVER, RFC 2131 section 4.4.1
+    let mut selecting = with_parameters(request(MessageType::DHCPREQUEST, mac))
+        .set_option(address_option(Option::RequestedIPAddress, offer.yiaddr))
+        .set_option(server_identifier.clone());
+    selecting.xid = discover.xid;
+    let address = acknowledged(exchanger, &selecting, Exchange::Request, outcome)?;
+    outcome.address = Some(address);
+
+    for step in steps {
+        match step {
+            Step::Renew => {
+                let mut renewing = with_parameters(request(MessageType::DHCPREQUEST, mac));
+                renewing.ciaddr = address;
+                acknowledged(exchanger, &renewing, Exchange::Renew, outcome)?;
+            }
+            Step::Release => {
+                let mut release =
+                    request(MessageType::DHCPRELEASE, mac).set_option(server_identifier.clone());
+                release.ciaddr = address;
+                exchanger.send(&release)?;
+            }
+            Step::Decline => {
+                let decline = request(MessageType::DHCPDECLINE, mac)
+                    .set_option(address_option(Option::RequestedIPAddress, address))
+                    .set_option(server_identifier.clone());
+                exchanger.send(&decline)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send a DHCPREQUEST, the address of the DHCPACK is returned
+fn acknowledged(
+    exchanger: &Exchanger,
+    request: &Message,
+    exchange: Exchange,
+    outcome: &mut Outcome,
+) -> Result<Ipv4Addr, Failure> {
+    let expected = [MessageType::DHCPACK, MessageType::DHCPNAK];
+    let (reply, latency) = exchanger.exchange(request, &expected)?;
+    outcome.latencies.push((exchange, latency));
+    if reply.message_type() == Some(MessageType::DHCPNAK) {
+        let message = reply
+            .option(Option::Message)
+            .map(|op| String::from_utf8_lossy(&op.data).to_string())
+            .unwrap_or_default();
+        return Err(Failure::Nak(message));
+    }
+    Ok(reply.yiaddr)
+}
+
+/// Client message with a new transaction ID, the only option is its type
+fn request(message_type: MessageType, mac: [u8; 6]) -> Message {
+    let mut chaddr = [0u8; 16];
+    chaddr[..6].copy_from_slice(&mac);
+    Message {
+        op: OpCode::BOOTREQUEST as u8,
+        htype: 1,
+        hlen: 6,
+        hops: 0,
+        xid: rand::random(),
+        secs: 0,
+        flags: 0,
+        ciaddr: Ipv4Addr::UNSPECIFIED,
+        yiaddr: Ipv4Addr::UNSPECIFIED,
+        siaddr: Ipv4Addr::UNSPECIFIED,
+        giaddr: Ipv4Addr::UNSPECIFIED,
+        chaddr,
+        sname: [0u8; 64],
+        file: [0u8; 128],
+        options: OptionField::new(vec![OptionSubfield::new(
+            Option::DHCPMessageType,
+            vec![message_type as u8],
+        )
+        .expect("message type has a length of 1")]),
+    }
+}
+
+fn with_parameters(message: Message) -> Message {
+    let codes: Vec<u8> = PARAMETER_REQUEST_LIST.iter().map(|op| *op as u8).collect();
+    let option = OptionSubfield::new(Option::ParameterRequestList, codes)
+        .expect("the parameter request list is short");
+    message.set_option(option)
+}
+
+fn address_option(op: Option, addr: Ipv4Addr) -> OptionSubfield {
+    OptionSubfield::new(op, addr.octets().to_vec()).expect("addresses have a length of 4")
+}
@@ -0,0 +1,62 @@
+mod cli;
+mod flow;
+mod report;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flow::{Exchanger, Outcome};
+use report::Report;
+
+fn main() {
+    let args = cli::handle();
+    if let Err(e) = run(&args) {
+        eprintln!("ERR: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Run the clients, `concurrency` at a time, then print the report
+fn run(args: &cli::Args) -> Result<(), String> {
+    let exchanger =
+        Exchanger::new(args).map_err(|e| format!("unable to bind {} : {}", args.bind, e))?;
+    let next = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let started = Instant::now();
+    let (outcomes, elapsed): (Vec<Outcome>, Duration) = thread::scope(|scope| {
+        scope.spawn(|| exchanger.dispatch(&done));
+        let workers: Vec<_> = (0..args.concurrency.clamp(1, args.clients.max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut outcomes = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= args.clients {
+                            return outcomes;
+                        }
+                        let mac = cli::nth_mac(args.mac, index);
+                        let outcome = flow::run(&exchanger, mac, &args.steps);
+                        if args.verbose {
+                            println!("{}", outcome);
+                        }
+                        outcomes.push(outcome);
+                    }
+                })
+            })
+            .collect();
+        let outcomes = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("client thread"))
+            .collect();
+        done.store(true, Ordering::Relaxed);
+        (outcomes, started.elapsed())
+    });
+    let report = Report::new(&outcomes, elapsed);
+    print!("{}", report);
+    match report.failed() {
+        0 => Ok(()),
+        1 => Err("1 client failed".to_string()),
+        count => Err(format!("{} clients failed", count)),
+    }
+}
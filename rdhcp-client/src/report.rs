@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::flow::{Exchange, Failure, Outcome};
+
+/// Success rate of the clients and latency of each exchange
+pub struct Report {
+    pub clients: usize,
+    pub succeeded: usize,
+    pub failures: BTreeMap<Failure, usize>,
+    pub latencies: BTreeMap<Exchange, Vec<Duration>>,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn new(outcomes: &[Outcome], elapsed: Duration) -> Report {
+        let mut report = Report {
+            clients: outcomes.len(),
+            succeeded: 0,
+            failures: BTreeMap::new(),
+            latencies: BTreeMap::new(),
+            elapsed,
+        };
+        for outcome in outcomes {
+            match &outcome.result {
+                Ok(()) => report.succeeded += 1,
+                Err(failure) => *report.failures.entry(failure.clone()).or_default() += 1,
+            }
+            for (exchange, latency) in outcome.latencies.iter() {
+                report
+                    .latencies
+                    .entry(*exchange)
+                    .or_default()
+                    .push(*latency);
+            }
+        }
+        for latencies in report.latencies.values_mut() {
+            latencies.sort();
+        }
+        report
+    }
+    pub fn failed(&self) -> usize {
+        self.clients - self.succeeded
+    }
+}
+
+/// Share of `count` in `total`, in percent
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+/// Latency under which `quantile` of the sorted latencies are, in ms
+fn quantile(sorted: &[Duration], quantile: f64) -> f64 {
+    let index = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+    milliseconds(sorted[index])
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "clients    {:>8} in {:.2} s, {:.1} clients/s",
+            self.clients,
+            seconds,
+            if seconds > 0.0 {
+                self.clients as f64 / seconds
+            } else {
+                0.0
+            }
+        )?;
+        writeln!(
+            f,
+            "succeeded  {:>8} ({:.1} %)",
+            self.succeeded,
+            percent(self.succeeded, self.clients)
+        )?;
+        writeln!(
+            f,
+            "failed     {:>8} ({:.1} %)",
+            self.failed(),
+            percent(self.failed(), self.clients)
+        )?;
+        for (failure, count) in self.failures.iter() {
+            writeln!(f, "  {:>8} {}", count, failure)?;
+        }
+        if self.latencies.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            f,
+            "\n{:<16} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "latency (ms)", "count", "min", "avg", "p50", "p90", "p99", "max"
+        )?;
+        for (exchange, sorted) in self.latencies.iter() {
+            let total: Duration = sorted.iter().sum();
+            writeln!(
+                f,
+                "{:<16} {:>8} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+                exchange,
+                sorted.len(),
+                quantile(sorted, 0.0),
+                milliseconds(total) / sorted.len() as f64,
+                quantile(sorted, 0.5),
+                quantile(sorted, 0.9),
+                quantile(sorted, 0.99),
+                quantile(sorted, 1.0),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! The client against a server running in the test, with the default
+//! configuration : 100 addresses, no conflict detection.
+
+use rdhcp::configuration::Configuration;
+use rdhcp::server::DhcpServer;
+use std::net::SocketAddr;
+use std::process::{Command, Output};
+use std::thread;
+
+/// Start a server on a free port of the loopback
+fn server() -> SocketAddr {
+    let server = DhcpServer::new("127.0.0.1:0".parse().unwrap());
+    let address = server.local_addr().expect("server address");
+    thread::spawn(move || server.on_recv(Configuration::default()));
+    address
+}
+
+fn client(server: SocketAddr, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rdhcp-client"))
+        .arg("--server")
+        .arg(server.to_string())
+        .args(["--timeout", "200"])
+        .args(args)
+        .output()
+        .expect("rdhcp-client")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn every_flow() {
+    let server = server();
+    let output = client(server, &["--clients", "50", "renew", "release"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("succeeded        50 (100.0 %)"));
+    assert!(stdout(&output).contains("RENEW-ACK"));
+
+    // The released addresses are given again
+    let output = client(server, &["--clients", "100", "--mac", "02:00:00:01:00:00"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    let output = client(
+        server,
+        &["--clients", "1", "--mac", "02:00:00:01:00:00", "decline"],
+    );
+    assert!(output.status.success(), "{}", stdout(&output));
+}
+
+#[test]
+fn pool_exhausted() {
+    let server = server();
+    let output = client(server, &["--clients", "110", "--retries", "0"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("succeeded       100 (90.9 %)"));
+    assert!(stdout(&output).contains("10 no DHCPOFFER"));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "ERR: 10 clients failed"
+    );
+}
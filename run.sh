@@ -7,7 +7,7 @@ if [[ $status != 0 ]]; then
 fi
 
 sysname=$(uname -s)
-confpath=$(readlink -f sample/dhcpd.conf)
+confpath=$(readlink -f sample/rdhcp.toml)
 if [[ "$sysname" == "Darwin" ]]; then
 	sudo ./target/release/rdhcp --conf $confpath
 else
@@ -0,0 +1,259 @@
+//! HTTP management API, every answer is a JSON document.
+//!
+//! GET    /leases                  leases, filtered by the "mac", "address"
+//!                                 and "hostname" query parameters
+//! DELETE /leases/<address>        release a lease
+//! GET    /history/<mac>           last events of a client, kept for the
+//!                                 clients seen most recently
+//! GET    /pools                   utilisation of the ranges of each subnet
+//! GET    /reservations            static reservations
+//! POST   /reservations            add a reservation :
+//!                                 {"subnet", "hardware_address", "fixed_address"}
+//! DELETE /reservations/<mac>      remove a reservation
+//! POST   /reload                  read the configuration file again, answers
+//!                                 the bound leases now outside of the ranges
+//! POST   /failover/partner-down   confirm the failover peer is down
+//!
+//! Requests are answered by the server loop, so the leases are never
+//! accessed from two threads. Reservations changed through the API are kept
+//! in memory only and lost on reload.
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::Ipv4Addr;
+use std::sync::mpsc::{self, Sender};
+use tiny_http::{Header, Method, Response, Server};
+use tracing::error;
+
+use crate::configuration::{Api, Host};
+
+/// What the API asks the server loop to do
+pub enum Request {
+    Leases(Filter),
+    Release(Ipv4Addr),
+    History(String),
+    Pools,
+    Reservations,
+    AddReservation(Reservation),
+    RemoveReservation(String),
+    Reload,
+    PartnerDown,
+}
+
+/// Answer of the server loop, or an HTTP status and an error message
+pub type Reply = Result<Value, (u16, String)>;
+
+pub struct Command {
+    pub request: Request,
+    pub reply: Sender<Reply>,
+}
+
+/// Search criteria of the leases, all set fields must match
+#[derive(Default)]
+pub struct Filter {
+    pub mac: Option<String>,
+    pub address: Option<Ipv4Addr>,
+    /// Part of the hostname
+    pub hostname: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Lease {
+    pub hardware_address: String,
+    pub address: Ipv4Addr,
+    pub hostname: String,
+    /// "offered" until the client sends its DHCPREQUEST, then "bound"
+    pub state: &'static str,
+    /// End of the lease as a unix timestamp, once bound
+    pub expire: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    /// Unix timestamp
+    pub time: u64,
+    /// "offered", "bound", "refused", "released" or "expired"
+    pub event: &'static str,
+    pub address: Ipv4Addr,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RangeUsage {
+    pub start_address: Ipv4Addr,
+    pub end_address: Ipv4Addr,
+    pub size: usize,
+    /// Offered, bound or abandoned addresses
+    pub used: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubnetUsage {
+    pub network: Ipv4Addr,
+    pub subnet_mask: Ipv4Addr,
+    pub size: usize,
+    pub used: usize,
+    pub ranges: Vec<RangeUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Reservation {
+    /// Network of the subnet the host belongs to
+    pub subnet: Ipv4Addr,
+    #[serde(flatten)]
+    pub host: Host,
+}
+
+pub struct ApiServer {
+    server: Server,
+    token: String,
+}
+
+impl Filter {
+    fn from_query(query: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        for (name, value) in query
+            .split('&')
+            .filter_map(|parameter| parameter.split_once('='))
+        {
+            let value = percent_decode(value);
+            match name {
+                "mac" => filter.mac = Some(value.to_ascii_lowercase()),
+                "address" => {
+                    filter.address = Some(value.parse().map_err(|_| "invalid address")?)
+                }
+                "hostname" => filter.hostname = Some(value.to_ascii_lowercase()),
+                _ => return Err(format!("unknown parameter {}", name)),
+            }
+        }
+        Ok(filter)
+    }
+    pub fn matches(&self, lease: &Lease) -> bool {
+        self.mac
+            .as_ref()
+            .is_none_or(|mac| lease.hardware_address.eq_ignore_ascii_case(mac))
+            && self.address.is_none_or(|address| lease.address == address)
+            && self
+                .hostname
+                .as_ref()
+                .is_none_or(|hostname| lease.hostname.contains(hostname.as_str()))
+    }
+}
+
+impl ApiServer {
+    pub fn new(configuration: &Api) -> Result<ApiServer, String> {
+        // "Authorization: Bearer " would be accepted
+        if configuration.token.is_empty() {
+            return Err("the API token is empty".to_string());
+        }
+        Ok(ApiServer {
+            server: Server::http(configuration.address).map_err(|e| e.to_string())?,
+            token: configuration.token.clone(),
+        })
+    }
+    pub fn on_recv(&self, commands: Sender<Command>) {
+        for mut request in self.server.incoming_requests() {
+            let reply = if self.authorized(&request) {
+                let mut body = String::new();
+                match request.as_reader().read_to_string(&mut body) {
+                    Ok(_) => route(request.method(), request.url(), &body)
+                        .and_then(|api_request| execute(&commands, api_request)),
+                    Err(e) => Err((400, e.to_string())),
+                }
+            } else {
+                Err((401, "missing or invalid token".to_string()))
+            };
+            let (status, document) = match reply {
+                Ok(document) => (200, document),
+                Err((status, message)) => (status, json!({ "error": message })),
+            };
+            let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("static header is valid");
+            let response = Response::from_string(document.to_string())
+                .with_status_code(status)
+                .with_header(content_type);
+            if let Err(e) = request.respond(response) {
+                error!(error = %e, "API unable to answer");
+            }
+        }
+    }
+    fn authorized(&self, request: &tiny_http::Request) -> bool {
+        let expected = format!("Bearer {}", self.token);
+        request
+            .headers()
+            .iter()
+            .filter(|header| header.field.equiv("Authorization"))
+            .any(|header| constant_time_eq(header.value.as_str().as_bytes(), expected.as_bytes()))
+    }
+}
+
+fn route(method: &Method, url: &str, body: &str) -> Result<Request, (u16, String)> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let bad_request = |message: String| (400, message);
+    match (method, segments.as_slice()) {
+        (Method::Get, ["leases"]) => Filter::from_query(query)
+            .map(Request::Leases)
+            .map_err(bad_request),
+        (Method::Delete, ["leases", address]) => address
+            .parse()
+            .map(Request::Release)
+            .map_err(|_| bad_request(format!("invalid address {}", address))),
+        (Method::Get, ["history", mac]) => {
+            Ok(Request::History(percent_decode(mac).to_ascii_lowercase()))
+        }
+        (Method::Get, ["pools"]) => Ok(Request::Pools),
+        (Method::Get, ["reservations"]) => Ok(Request::Reservations),
+        (Method::Post, ["reservations"]) => serde_json::from_str(body)
+            .map(Request::AddReservation)
+            .map_err(|e| bad_request(e.to_string())),
+        (Method::Delete, ["reservations", mac]) => {
+            Ok(Request::RemoveReservation(percent_decode(mac).to_ascii_lowercase()))
+        }
+        (Method::Post, ["reload"]) => Ok(Request::Reload),
+        (Method::Post, ["failover", "partner-down"]) => Ok(Request::PartnerDown),
+        _ => Err((404, format!("no route for {} {}", method, path))),
+    }
+}
+
+/// Hand the request to the server loop and wait for its answer
+fn execute(commands: &Sender<Command>, request: Request) -> Reply {
+    let (reply, answer) = mpsc::channel();
+    let unavailable = || (503, "server loop stopped".to_string());
+    commands
+        .send(Command { request, reply })
+        .map_err(|_| unavailable())?;
+    answer.recv().map_err(|_| unavailable())?
+}
+
+/// "%3A" and friends in query parameters and paths
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Compare without leaking where the first difference is
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,70 @@
+//! Load balancing between servers sharing a network, RFC 3074.
+//!
+//! Each client is hashed into one of 256 buckets from its client identifier
+//! (option 61), or its hardware address when it doesn't send one. The
+//! buckets are split in contiguous blocks between the servers, each one only
+//! answering the clients of its own block. A client which has been trying
+//! for more than `secs` seconds is answered anyway, in case its server is
+//! down.
+
+use crate::configuration::LoadBalancing;
+use dhcp_proto::options::*;
+use dhcp_proto::{MessageRef, MessageType};
+
+const BUCKETS: usize = 256;
+
+/// Mixing table of the Pearson hash given in RFC 3074 section 6
+const MIX_TABLE: [u8; BUCKETS] = [
+    251, 175, 119, 215, 81, 14, 79, 191, 103, 49, 181, 143, 186, 157, 0, 232, 31, 32, 55, 60,
+    152, 58, 17, 237, 174, 70, 160, 144, 220, 90, 57, 223, 59, 3, 18, 140, 111, 166, 203, 196,
+    134, 243, 124, 95, 222, 179, 197, 65, 180, 48, 36, 15, 107, 46, 233, 130, 165, 30, 123,
+    161, 209, 23, 97, 16, 40, 91, 219, 61, 100, 10, 210, 109, 250, 127, 22, 138, 29, 108, 244,
+    67, 207, 9, 178, 204, 74, 98, 126, 249, 167, 116, 34, 77, 193, 200, 121, 5, 20, 113, 71,
+    35, 128, 13, 182, 94, 25, 226, 227, 199, 75, 27, 41, 245, 230, 224, 43, 225, 177, 26, 155,
+    150, 212, 142, 218, 115, 241, 73, 88, 105, 39, 114, 62, 255, 192, 201, 145, 214, 168, 158,
+    221, 148, 154, 122, 12, 84, 82, 163, 44, 139, 228, 236, 205, 242, 217, 11, 187, 146, 159,
+    64, 86, 239, 195, 42, 106, 198, 118, 112, 184, 172, 87, 2, 173, 117, 176, 229, 247, 253,
+    137, 185, 99, 164, 102, 147, 45, 66, 231, 52, 141, 211, 194, 206, 246, 238, 56, 110, 78,
+    248, 63, 240, 189, 93, 92, 51, 53, 183, 19, 171, 72, 50, 33, 104, 101, 69, 8, 252, 83, 120,
+    76, 135, 85, 54, 202, 125, 188, 213, 96, 235, 136, 208, 162, 129, 190, 132, 156, 38, 47, 1,
+    7, 254, 24, 4, 216, 131, 89, 21, 28, 133, 37, 153, 149, 80, 170, 68, 6, 169, 234, 151,
+];
+
+/// Pearson hash of a key, starting from its last byte as in the RFC
+pub fn hash(key: &[u8]) -> u8 {
+    key.iter()
+        .rev()
+        .fold(key.len() as u8, |hash, byte| MIX_TABLE[usize::from(hash ^ byte)])
+}
+
+/// Bucket of a client
+pub fn bucket(msg: &MessageRef) -> u8 {
+    match msg.option(Option::ClientIdentifier) {
+        Some(client_identifier) if !client_identifier.is_empty() => hash(client_identifier),
+        _ => hash(msg.hardware_address()),
+    }
+}
+
+impl LoadBalancing {
+    /// Whether the bucket is in the block of this server
+    pub fn owns(&self, bucket: u8) -> bool {
+        let servers = usize::from(self.servers.max(1));
+        usize::from(bucket) * servers / BUCKETS == usize::from(self.index)
+    }
+    /**
+     * Whether this server answers the message. Only DHCPDISCOVER and the
+     * DHCPREQUEST of a rebooting client are balanced, the other ones are
+     * already sent to a single server.
+     */
+    pub fn accepts(&self, msg: &MessageRef) -> bool {
+        let balanced = match msg.message_type() {
+            Some(MessageType::DHCPDISCOVER) => true,
+            Some(MessageType::DHCPREQUEST) => {
+                msg.option(Option::ServerIdentifier).is_none()
+                    && msg.option(Option::RequestedIPAddress).is_some()
+            }
+            _ => false,
+        };
+        !balanced || msg.secs() > self.secs || self.owns(bucket(msg))
+    }
+}
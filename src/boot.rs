@@ -0,0 +1,113 @@
+//! Network boot parameters of the replies, the boot file is chosen by
+//! client architecture (option 93).
+//!
+//! The 'sname' and 'file' fields only ever hold names : options are never
+//! overloaded into them (option 52), a name too long for its field is sent
+//! as an option instead.
+
+use crate::configuration::{user_classes, Boot, Tftp};
+use dhcp_proto::options::*;
+use dhcp_proto::Message;
+use crate::tftp;
+use std::path::Path;
+use tracing::warn;
+
+// Length of the sname and file fields, minus the null terminator
+const SNAME_MAX: usize = 63;
+const FILE_MAX: usize = 127;
+
+/// Client system architecture, option 93 as registered by IANA (RFC 4578)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Architecture {
+    Bios,
+    UefiIa32,
+    UefiX64,
+    UefiArm32,
+    UefiArm64,
+}
+impl Architecture {
+    pub fn from_message(msg: &Message) -> std::option::Option<Architecture> {
+        let op = msg.option(Option::ClientSystemArchitecture)?;
+        // Clients may list several architectures, the first one is the preferred
+        let code = u16::from_be_bytes([*op.data.first()?, *op.data.get(1)?]);
+        Architecture::from_code(code)
+    }
+    pub fn from_code(code: u16) -> std::option::Option<Architecture> {
+        match code {
+            0 => Some(Architecture::Bios),
+            6 => Some(Architecture::UefiIa32),
+            7 | 9 | 16 => Some(Architecture::UefiX64),
+            10 | 18 => Some(Architecture::UefiArm32),
+            11 | 19 => Some(Architecture::UefiArm64),
+            _ => None,
+        }
+    }
+}
+
+/// iPXE identifies itself with the "iPXE" user class
+pub fn is_ipxe(msg: &Message) -> bool {
+    user_classes(msg).iter().any(|class| *class == b"iPXE")
+}
+
+/**
+ * Fill the boot parameters of a reply.
+ *
+ * The next server always goes in 'siaddr'. The server name and the boot file
+ * name go in the 'sname' and 'file' fields when they fit, otherwise they are
+ * sent as options 66 and 67. The options are also sent when the client asks
+ * for them in its parameter request list.
+ *
+ * When the built-in TFTP server is enabled, a file it can't serve is not
+ * offered, URLs are left alone.
+ */
+pub fn apply(
+    boot: &Boot,
+    tftp: std::option::Option<&Tftp>,
+    request: &Message,
+    mut response: Message,
+) -> Message {
+    if let Some(next_server) = boot.next_server {
+        response.siaddr = next_server;
+    }
+    let requested = |op: Option| match request.option(Option::ParameterRequestList) {
+        Some(list) => list.data.contains(&(op as u8)),
+        None => false,
+    };
+
+    if let Some(server_name) = &boot.server_name {
+        if server_name.len() <= SNAME_MAX {
+            response.sname = [0u8; 64];
+            response.sname[..server_name.len()].copy_from_slice(server_name.as_bytes());
+        }
+        if server_name.len() > SNAME_MAX || requested(Option::TFTPServerName) {
+            if let Ok(op) = OptionSubfield::new(Option::TFTPServerName, server_name.clone().into()) {
+                response = response.add_options(op);
+            }
+        }
+    }
+    let filename = boot.filename(Architecture::from_message(request), is_ipxe(request));
+    if let Some(filename) = filename.filter(|filename| is_available(tftp, filename)) {
+        if filename.len() <= FILE_MAX {
+            response.file = [0u8; 128];
+            response.file[..filename.len()].copy_from_slice(filename.as_bytes());
+        }
+        if filename.len() > FILE_MAX || requested(Option::BootfileName) {
+            if let Ok(op) = OptionSubfield::new(Option::BootfileName, filename.into()) {
+                response = response.add_options(op);
+            }
+        }
+    }
+    response
+}
+
+fn is_available(tftp: std::option::Option<&Tftp>, filename: &str) -> bool {
+    let tftp = match tftp {
+        Some(tftp) if !filename.contains("://") => tftp,
+        _ => return true,
+    };
+    let available = tftp::resolve(Path::new(&tftp.root), filename).is_some();
+    if !available {
+        warn!(filename, root = %tftp.root, "boot file not found, not offering it");
+    }
+    available
+}
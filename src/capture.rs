@@ -0,0 +1,366 @@
+//! Capture of the DHCP packets in the pcap format, to be opened with
+//! Wireshark or tcpdump.
+//!
+//! The server only sees UDP payloads, so each packet is wrapped in made up
+//! Ethernet, IPv4 and UDP headers : the addresses and ports are the real
+//! ones, the client MAC address comes from chaddr and the server one is
+//! SERVER_MAC. A server listening on all interfaces doesn't know where a
+//! packet was sent : it was broadcast unless it comes from a relay or from a
+//! client with an address, which send it to the server identifier.
+//!
+//! Once the file grows over `max_size` it is renamed to "<path>.1", the
+//! previous "<path>.1" to "<path>.2" and so on, up to `max_files`.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+use crate::configuration::Capture;
+use crate::probe::checksum;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IPPROTO_UDP: u8 = 17;
+const SERVER_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+pub struct PacketCapture {
+    configuration: Capture,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl PacketCapture {
+    pub fn new(configuration: &Capture) -> io::Result<PacketCapture> {
+        let (file, size) = create(&configuration.path)?;
+        Ok(PacketCapture {
+            configuration: configuration.clone(),
+            file,
+            size,
+        })
+    }
+    /// Packet received from a client on the `local` socket
+    pub fn received(
+        &mut self,
+        payload: &[u8],
+        source: SocketAddr,
+        local: SocketAddr,
+        server_identifier: Ipv4Addr,
+    ) {
+        let destination = match local.ip() {
+            IpAddr::V4(addr) if addr.is_unspecified() => {
+                let relayed_or_bound = [12..16, 24..28]
+                    .into_iter()
+                    .any(|field| payload.get(field).is_some_and(|addr| addr != [0; 4]));
+                let addr = if relayed_or_bound {
+                    server_identifier
+                } else {
+                    Ipv4Addr::BROADCAST
+                };
+                SocketAddr::new(IpAddr::V4(addr), local.port())
+            }
+            _ => local,
+        };
+        let destination_mac = match destination.ip() {
+            IpAddr::V4(addr) if addr.is_broadcast() => BROADCAST_MAC,
+            _ => SERVER_MAC,
+        };
+        self.write(
+            payload,
+            client_mac(payload),
+            destination_mac,
+            source,
+            destination,
+        );
+    }
+    /// Packet sent to a client
+    pub fn sent(&mut self, payload: &[u8], source: SocketAddr, destination: SocketAddr) {
+        let destination_mac = match destination.ip() {
+            IpAddr::V4(addr) if addr.is_broadcast() => BROADCAST_MAC,
+            _ => client_mac(payload),
+        };
+        self.write(payload, SERVER_MAC, destination_mac, source, destination);
+    }
+    fn write(
+        &mut self,
+        payload: &[u8],
+        source_mac: [u8; 6],
+        destination_mac: [u8; 6],
+        source: SocketAddr,
+        destination: SocketAddr,
+    ) {
+        let frame = match (source, destination) {
+            (SocketAddr::V4(source), SocketAddr::V4(destination)) => {
+                frame(payload, source_mac, destination_mac, source, destination)
+            }
+            _ => return,
+        };
+        if let Err(e) = self.write_record(&frame) {
+            error!(path = %self.configuration.path, error = %e, "unable to write the capture");
+        }
+    }
+    fn write_record(&mut self, frame: &[u8]) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record: Vec<u8> = Vec::with_capacity(16 + frame.len());
+        record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes()); // captured
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes()); // original
+        record.extend_from_slice(frame);
+        if self.size + record.len() as u64 > self.configuration.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(&record)?;
+        // Flushed each time so the file can be read while the server runs
+        self.file.flush()?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.configuration.path;
+        if self.configuration.max_files > 0 {
+            for index in (1..self.configuration.max_files).rev() {
+                let _ = fs::rename(
+                    format!("{}.{}", path, index),
+                    format!("{}.{}", path, index + 1),
+                );
+            }
+            fs::rename(path, format!("{}.1", path))?;
+        }
+        let (file, size) = create(path)?;
+        self.file = file;
+        self.size = size;
+        Ok(())
+    }
+}
+
+/// New capture file, starting with the pcap global header
+fn create(path: &str) -> io::Result<(BufWriter<File>, u64)> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header: Vec<u8> = Vec::with_capacity(24);
+    header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes()); // major version
+    header.extend_from_slice(&4u16.to_le_bytes()); // minor version
+    header.extend_from_slice(&0i32.to_le_bytes()); // GMT offset
+    header.extend_from_slice(&0u32.to_le_bytes()); // timestamps accuracy
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    file.write_all(&header)?;
+    file.flush()?;
+    Ok((file, header.len() as u64))
+}
+
+/// chaddr of a DHCP message, when it is an Ethernet address
+fn client_mac(payload: &[u8]) -> [u8; 6] {
+    match (payload.get(1..3), payload.get(28..34)) {
+        (Some([1, 6]), Some(chaddr)) => chaddr.try_into().unwrap_or(BROADCAST_MAC),
+        _ => BROADCAST_MAC,
+    }
+}
+
+fn frame(
+    payload: &[u8],
+    source_mac: [u8; 6],
+    destination_mac: [u8; 6],
+    source: std::net::SocketAddrV4,
+    destination: std::net::SocketAddrV4,
+) -> Vec<u8> {
+    let udp_length = 8 + payload.len() as u16;
+    let ip_length = 20 + udp_length;
+
+    let mut ip: Vec<u8> = vec![0x45, 0];
+    ip.extend_from_slice(&ip_length.to_be_bytes());
+    ip.extend_from_slice(&[0, 0, 0, 0]); // identification, flags and fragment
+    ip.extend_from_slice(&[64, IPPROTO_UDP, 0, 0]); // TTL, protocol, checksum
+    ip.extend_from_slice(&source.ip().octets());
+    ip.extend_from_slice(&destination.ip().octets());
+    let ip_checksum = checksum(&ip);
+    ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    let mut frame: Vec<u8> = Vec::with_capacity(14 + ip_length as usize);
+    frame.extend_from_slice(&destination_mac);
+    frame.extend_from_slice(&source_mac);
+    frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    frame.extend(ip);
+    frame.extend_from_slice(&source.port().to_be_bytes());
+    frame.extend_from_slice(&destination.port().to_be_bytes());
+    frame.extend_from_slice(&udp_length.to_be_bytes());
+    frame.extend_from_slice(
+        &udp_checksum(
+            payload,
+            *source.ip(),
+            *destination.ip(),
+            source.port(),
+            destination.port(),
+        )
+        .to_be_bytes(),
+    );
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Checksum over the IPv4 pseudo header, the UDP header and the payload
+fn udp_checksum(
+    payload: &[u8],
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    source_port: u16,
+    destination_port: u16,
+) -> u16 {
+    let udp_length = 8 + payload.len() as u16;
+    let mut data: Vec<u8> = Vec::with_capacity(20 + payload.len());
+    data.extend_from_slice(&source.octets());
+    data.extend_from_slice(&destination.octets());
+    data.extend_from_slice(&[0, IPPROTO_UDP]);
+    data.extend_from_slice(&udp_length.to_be_bytes());
+    data.extend_from_slice(&source_port.to_be_bytes());
+    data.extend_from_slice(&destination_port.to_be_bytes());
+    data.extend_from_slice(&udp_length.to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(payload);
+    match checksum(&data) {
+        // 0 means no checksum, RFC 768
+        0 => 0xffff,
+        sum => sum,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const CHADDR: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn capture(name: &str, max_size: u64) -> (PacketCapture, String) {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "rdhcp-capture-{}-{}.pcap",
+                name,
+                std::process::id()
+            ))
+            .display()
+            .to_string();
+        for index in 1..4 {
+            let _ = fs::remove_file(format!("{}.{}", path, index));
+        }
+        let configuration = Capture {
+            path: path.clone(),
+            max_size,
+            max_files: 2,
+        };
+        (PacketCapture::new(&configuration).unwrap(), path)
+    }
+
+    /// BOOTREQUEST from CHADDR, with `ciaddr`
+    fn request(ciaddr: [u8; 4]) -> Vec<u8> {
+        let mut payload = vec![0u8; 240];
+        payload[..3].copy_from_slice(&[1, 1, 6]);
+        payload[12..16].copy_from_slice(&ciaddr);
+        payload[28..34].copy_from_slice(&CHADDR);
+        payload
+    }
+
+    fn receive(capture: &mut PacketCapture, payload: &[u8]) {
+        capture.received(
+            payload,
+            "0.0.0.0:68".parse().unwrap(),
+            "0.0.0.0:67".parse().unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1),
+        );
+    }
+
+    /// Frames of the records following the global header
+    fn frames(file: &[u8]) -> Vec<&[u8]> {
+        let mut frames = Vec::new();
+        let mut records = &file[24..];
+        while !records.is_empty() {
+            let captured = u32::from_le_bytes(records[8..12].try_into().unwrap()) as usize;
+            let original = u32::from_le_bytes(records[12..16].try_into().unwrap()) as usize;
+            assert_eq!(captured, original);
+            frames.push(&records[16..16 + captured]);
+            records = &records[16 + captured..];
+        }
+        frames
+    }
+
+    #[test]
+    fn pcap_format() {
+        let (mut capture, path) = capture("format", 1 << 20);
+        let payload = request([0; 4]);
+        receive(&mut capture, &payload);
+        capture.sent(
+            &payload,
+            "192.0.2.1:67".parse().unwrap(),
+            "192.0.2.10:68".parse().unwrap(),
+        );
+        let file = fs::read(&path).unwrap();
+        assert_eq!(file[..4], PCAP_MAGIC.to_le_bytes());
+        assert_eq!(file[4..8], [2, 0, 4, 0]);
+        assert_eq!(file[16..20], SNAPLEN.to_le_bytes());
+        assert_eq!(file[20..24], LINKTYPE_ETHERNET.to_le_bytes());
+
+        let frames = frames(&file);
+        assert_eq!(frames.len(), 2);
+        // Broadcast by the client, received on the wildcard address
+        let frame = frames[0];
+        assert_eq!(frame.len(), 14 + 20 + 8 + payload.len());
+        assert_eq!(frame[..6], BROADCAST_MAC);
+        assert_eq!(frame[6..12], CHADDR);
+        assert_eq!(frame[12..14], ETHERTYPE_IPV4.to_be_bytes());
+        let ip = &frame[14..34];
+        assert_eq!(checksum(ip), 0);
+        assert_eq!(ip[9], IPPROTO_UDP);
+        assert_eq!(ip[16..20], [255, 255, 255, 255]);
+        assert_eq!(frame[34..38], [0, 68, 0, 67]);
+        assert_eq!(&frame[42..], payload.as_slice());
+        // Unicast to the client
+        let frame = frames[1];
+        assert_eq!(frame[..6], CHADDR);
+        assert_eq!(frame[6..12], SERVER_MAC);
+        assert_eq!(frame[26..34], [192, 0, 2, 1, 192, 0, 2, 10]);
+        assert_eq!(frame[34..38], [0, 67, 0, 68]);
+    }
+
+    #[test]
+    fn unicast_destination() {
+        let (mut capture, path) = capture("unicast", 1 << 20);
+        // A renewing client sends its request to the server
+        receive(&mut capture, &request([192, 0, 2, 10]));
+        capture.received(
+            &request([0; 4]),
+            "192.0.2.10:68".parse().unwrap(),
+            "192.0.2.2:67".parse().unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1),
+        );
+        let file = fs::read(&path).unwrap();
+        let frames = frames(&file);
+        assert_eq!(frames[0][..6], SERVER_MAC);
+        assert_eq!(frames[0][30..34], [192, 0, 2, 1]);
+        // The address of the socket when it is bound to one
+        assert_eq!(frames[1][30..34], [192, 0, 2, 2]);
+    }
+
+    #[test]
+    fn rotate() {
+        let payload = request([0; 4]);
+        let record = 16 + 14 + 20 + 8 + payload.len() as u64;
+        // Room for two records per file
+        let (mut capture, path) = capture("rotate", 24 + 2 * record);
+        for _ in 0..7 {
+            receive(&mut capture, &payload);
+        }
+        let records = |path: &str| frames(&fs::read(path).unwrap()).len();
+        assert_eq!(records(&path), 1);
+        assert_eq!(records(&format!("{}.1", path)), 2);
+        assert_eq!(records(&format!("{}.2", path)), 2);
+        // Only max_files old files are kept
+        assert!(!Path::new(&format!("{}.3", path)).exists());
+    }
+}
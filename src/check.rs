@@ -0,0 +1,134 @@
+//! Check of a configuration file, every problem found is reported as
+//! "<path>:<line>:<column>: <message>".
+//!
+//! The configuration is parsed a second time into `Layout`, which only keeps
+//! the position of the values problems are reported on.
+
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use toml::Spanned;
+
+use crate::configuration::{Configuration, Location};
+
+#[derive(Deserialize, Default)]
+struct Layout {
+    listen: Option<Spanned<toml::Value>>,
+    server_identifier: Option<Spanned<toml::Value>>,
+    #[serde(default, rename = "subnet")]
+    subnets: Vec<SubnetLayout>,
+    #[serde(default, rename = "class")]
+    classes: Vec<ClassLayout>,
+    load_balancing: Option<LoadBalancingLayout>,
+    failover: Option<FailoverLayout>,
+    api: Option<ApiLayout>,
+}
+
+#[derive(Deserialize)]
+struct SubnetLayout {
+    #[serde(default, rename = "range")]
+    ranges: Vec<RangeLayout>,
+    #[serde(default, rename = "host")]
+    hosts: Vec<HostLayout>,
+    #[serde(default)]
+    options: BTreeMap<String, Spanned<toml::Value>>,
+    #[serde(default)]
+    lease: BTreeMap<String, Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+struct RangeLayout {
+    start_address: Spanned<toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct HostLayout {
+    hardware_address: Spanned<toml::Value>,
+    #[serde(default)]
+    lease: BTreeMap<String, Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+struct ClassLayout {
+    #[serde(default)]
+    options: BTreeMap<String, Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+struct LoadBalancingLayout {
+    index: Spanned<toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct FailoverLayout {
+    secret: Spanned<toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct ApiLayout {
+    token: Spanned<toml::Value>,
+}
+
+impl Layout {
+    fn span(&self, location: &Location) -> Option<Range<usize>> {
+        let span = match location {
+            Location::Listen => self.listen.as_ref()?.span(),
+            Location::ServerIdentifier => self.server_identifier.as_ref()?.span(),
+            Location::LoadBalancing => self.load_balancing.as_ref()?.index.span(),
+            Location::Failover => self.failover.as_ref()?.secret.span(),
+            Location::Api => self.api.as_ref()?.token.span(),
+            Location::Range(i, j) => self.subnets.get(*i)?.ranges.get(*j)?.start_address.span(),
+            Location::Host(i, j) => self.subnets.get(*i)?.hosts.get(*j)?.hardware_address.span(),
+            Location::SubnetLease(i, field) => self.subnets.get(*i)?.lease.get(*field)?.span(),
+            Location::HostLease(i, j, field) => {
+                self.subnets.get(*i)?.hosts.get(*j)?.lease.get(*field)?.span()
+            }
+            Location::SubnetOption(i, name) => self.subnets.get(*i)?.options.get(name)?.span(),
+            Location::ClassOption(i, name) => self.classes.get(*i)?.options.get(name)?.span(),
+        };
+        Some(span)
+    }
+}
+
+/// Every problem of the configuration file, an empty list when it is valid
+pub fn check(path: &str) -> Vec<String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => return vec![format!("{}: unable to read : {}", path, e)],
+    };
+    let configuration: Configuration = match toml::from_str(&content) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            return vec![format!(
+                "{}: {}",
+                position(path, &content, e.span()),
+                e.message()
+            )]
+        }
+    };
+    let layout: Layout = toml::from_str(&content).unwrap_or_default();
+    configuration
+        .validate()
+        .iter()
+        .map(|problem| {
+            format!(
+                "{}: {}",
+                position(path, &content, layout.span(&problem.location)),
+                problem
+            )
+        })
+        .collect()
+}
+
+/// "<path>:<line>:<column>" of the start of a span, counted from 1
+fn position(path: &str, content: &str, span: Option<Range<usize>>) -> String {
+    let offset = match span {
+        Some(span) => span.start.min(content.len()),
+        None => return path.to_string(),
+    };
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+    format!("{}:{}:{}", path, line, column)
+}
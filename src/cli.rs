@@ -1,14 +1,83 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::{Ipv4Addr, SocketAddr};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    /// Configuration file, needed to run the server or validate it, and by
+    /// the API commands unless --api and --token are given
     #[arg(short, long = "conf")]
-    conf_file_path: String,
+    pub conf_file_path: Option<String>,
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Management API address, taken from the configuration by default
+    #[arg(long, global = true)]
+    pub api: Option<SocketAddr>,
+    /// Management API token, taken from the configuration by default
+    #[arg(long, global = true)]
+    pub token: Option<String>,
+    /// Report every problem of the configuration file and exit, like the
+    /// `validate` command
+    #[arg(long)]
+    pub check: bool,
+    #[arg(long, value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Run the server when missing
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
-pub fn handle() -> String {
-    let args = Args::parse();
-    println!("Loading configuration file : {}", args.conf_file_path);
-    args.conf_file_path
+/// Commands sent to a running server through its management API, except
+/// `validate`
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the leases
+    Leases {
+        /// Only the lease of this hardware address
+        #[arg(long)]
+        mac: Option<String>,
+        /// Only the leases whose hostname contains this
+        #[arg(long)]
+        hostname: Option<String>,
+    },
+    /// Show the last events of a client
+    History { mac: String },
+    /// Release a lease
+    Release { address: Ipv4Addr },
+    /// Confirm the failover peer is down, its addresses are allocated once
+    /// the MCLT has elapsed
+    PartnerDown,
+    /// Check the configuration file without starting the server
+    Validate,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Args {
+    /// Path of the configuration file, needed `purpose`
+    pub fn configuration_path(&self, purpose: &str) -> Result<&str, String> {
+        self.conf_file_path
+            .as_deref()
+            .ok_or_else(|| format!("--conf is required {}", purpose))
+    }
+}
+
+pub fn handle() -> Args {
+    Args::parse()
 }
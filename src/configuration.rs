@@ -1,28 +1,697 @@
-use chrono;
+use crate::boot::Architecture;
+use crate::failover::Role;
+use dhcp_proto::options::{self, OptionSubfield};
+use dhcp_proto::Message;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
 use std::{fs, net::Ipv4Addr};
 
-const CONFIGURATION_FILENAME: &str = "dhcp-server.toml";
+// Default lease set to 2h
+const DEFAULT_LEASE: u64 = 7200;
+const DEFAULT_MIN_LEASE: u64 = 300;
+const DEFAULT_MAX_LEASE: u64 = 86400;
+// T1 and T2 as a fraction of the lease, see RFC 2131 section 4.4.5
+const DEFAULT_RENEWAL: f64 = 0.5;
+const DEFAULT_REBINDING: f64 = 0.875;
 
 #[derive(Deserialize, Serialize)]
 pub struct Configuration {
-    pub range: AddressRange,
+    /// Address the DHCP server listens on
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+    /// Address given to the clients in the server identifier option, the
+    /// one listened on when missing
+    pub server_identifier: Option<Ipv4Addr>,
+    #[serde(rename = "subnet")]
+    pub subnets: Vec<Subnet>,
+    #[serde(default, rename = "class")]
+    pub classes: Vec<ClientClass>,
+    #[serde(default)]
+    pub conflict_detection: ConflictDetection,
+    /// Built-in TFTP server, disabled when missing
+    pub tftp: Option<Tftp>,
+    /// Dynamic DNS updates, disabled when missing
+    pub ddns: Option<Ddns>,
+    /// File the leases are dumped to after every change
+    pub leases_file: Option<String>,
+    /// Peer sharing the pools with this server, disabled when missing
+    pub failover: Option<Failover>,
+    /// Split of the clients between several servers, disabled when missing
+    pub load_balancing: Option<LoadBalancing>,
+    /// HTTP management API, disabled when missing
+    pub api: Option<Api>,
+    /// Prometheus metrics endpoint, disabled when missing
+    pub metrics: Option<Metrics>,
+    /// Write the DHCP packets to a pcap file, disabled when missing
+    pub capture: Option<Capture>,
+    #[serde(skip)]
     pub configuration_path: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Subnet {
+    pub network: Ipv4Addr,
+    pub subnet_mask: Ipv4Addr,
+    #[serde(rename = "range")]
+    pub ranges: Vec<AddressRange>,
+    #[serde(default)]
+    pub lease: LeaseTime,
+    #[serde(default, rename = "host")]
+    pub hosts: Vec<Host>,
+    #[serde(default)]
+    pub options: BTreeMap<String, OptionValue>,
+    /// If not empty, only clients of one of these classes are served
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    pub boot: Option<Boot>,
+    /// Append the domain-name option to the single label client hostnames
+    #[serde(default)]
+    pub append_domain: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AddressRange {
     pub start_address: Ipv4Addr,
     pub end_address: Ipv4Addr,
-    pub subnet_mask: Ipv4Addr,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Clients of these classes take their address from this range first
+    #[serde(default)]
+    pub prefer: Vec<String>,
+}
+
+/// Value of an option in the configuration, encoded according to the option code
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Flag(bool),
+    Number(u32),
+    Address(Ipv4Addr),
+    Addresses(Vec<Ipv4Addr>),
+    Text(String),
+}
+
+/// Group of clients sharing options and address ranges
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClientClass {
+    pub name: String,
+    #[serde(rename = "match")]
+    pub matcher: ClassMatch,
+    /// Options overriding the ones of the subnet
+    #[serde(default)]
+    pub options: BTreeMap<String, OptionValue>,
+}
+
+/// Every field set must match for the client to be part of the class
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ClassMatch {
+    /// Prefix of the vendor class identifier, option 60 (e.g. "PXEClient")
+    pub vendor_class: Option<String>,
+    /// One of the user classes, option 77
+    pub user_class: Option<String>,
+    /// Organizationally unique identifier, the first three bytes of the hardware address
+    pub oui: Option<String>,
+    /// Agent circuit ID sub-option of option 82
+    pub circuit_id: Option<String>,
+    /// Agent remote ID sub-option of option 82
+    pub remote_id: Option<String>,
+}
+
+/// Lease durations in seconds, the renewal and rebinding values are the
+/// fractions of the lease used to compute T1 and T2
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LeaseTime {
+    pub default: u64,
+    pub min: u64,
+    pub max: u64,
+    pub renewal: f64,
+    pub rebinding: f64,
+}
+
+/// Network boot parameters, the file depends on the client architecture
+/// (option 93) and falls back on `filename`
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Boot {
+    /// TFTP server, sent in 'siaddr'
+    pub next_server: Option<Ipv4Addr>,
+    pub server_name: Option<String>,
+    pub filename: Option<String>,
+    pub bios: Option<String>,
+    pub uefi_ia32: Option<String>,
+    pub uefi_x64: Option<String>,
+    pub uefi_arm32: Option<String>,
+    pub uefi_arm64: Option<String>,
+    /// Given instead of the file when the client is already running iPXE
+    /// (user class "iPXE"), so it doesn't chainload itself forever
+    pub ipxe_script: Option<String>,
+}
+
+/// Directory served by the built-in TFTP server, boot files are checked
+/// against it before being offered
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Tftp {
+    pub root: String,
+    #[serde(default = "default_tftp_address")]
+    pub address: SocketAddr,
+}
+
+/// Requests to the management API must carry the token in an
+/// "Authorization: Bearer" header
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Api {
+    #[serde(default = "default_api_address")]
+    pub address: SocketAddr,
+    pub token: String,
+}
+
+fn default_api_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8067))
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Metrics {
+    #[serde(default = "default_metrics_address")]
+    pub address: SocketAddr,
+}
+
+fn default_metrics_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9167))
+}
+
+/// Capture files are rotated once they reach `max_size` bytes, keeping
+/// `max_files` old ones
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Capture {
+    pub path: String,
+    #[serde(default = "default_capture_max_size")]
+    pub max_size: u64,
+    #[serde(default = "default_capture_max_files")]
+    pub max_files: u32,
+}
+
+fn default_capture_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_capture_max_files() -> u32 {
+    5
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 67))
+}
+
+fn default_tftp_address() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 69))
+}
+
+/// DNS server receiving the updates for the A and PTR records of the clients
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Ddns {
+    pub server: SocketAddr,
+    /// Forward zone, appended to the client hostnames
+    pub zone: String,
+    /// Zone of the PTR records, the /24 of the address when missing
+    pub reverse_zone: Option<String>,
+    #[serde(default = "default_ddns_ttl")]
+    pub ttl: u32,
+    /// Leave the A record to the clients asking for it with option 81, the
+    /// server always updates it otherwise
+    #[serde(default)]
+    pub client_updates: bool,
+    pub tsig: Option<TsigKey>,
+}
+
+fn default_ddns_ttl() -> u32 {
+    300
+}
+
+/// Key used to sign the updates, algorithm is one of hmac-md5, hmac-sha1
+/// or hmac-sha256
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TsigKey {
+    pub name: String,
+    #[serde(default = "default_tsig_algorithm")]
+    pub algorithm: String,
+    /// Base64 encoded secret, as generated by tsig-keygen
+    pub secret: String,
+}
+
+fn default_tsig_algorithm() -> String {
+    "hmac-sha256".into()
+}
+
+/// The primary listens on `address` and the secondary connects to it, delays
+/// are in seconds
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Failover {
+    pub role: Role,
+    pub address: SocketAddr,
+    /// Shared by both peers, which must prove they know it
+    pub secret: String,
+    #[serde(default = "default_heartbeat_timeout")]
+    pub heartbeat_timeout: u64,
+    /// Maximum client lead time : time in PARTNER-DOWN before allocating
+    /// the addresses of the peer
+    #[serde(default = "default_mclt")]
+    pub mclt: u64,
+}
+
+fn default_heartbeat_timeout() -> u64 {
+    5
+}
+
+fn default_mclt() -> u64 {
+    3600
+}
+
+/// This server is the `index`-th of `servers` (starting at 0) and only
+/// answers its share of the clients, unless they have been waiting for
+/// more than `secs` seconds
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LoadBalancing {
+    pub servers: u8,
+    pub index: u8,
+    #[serde(default = "default_balancing_secs")]
+    pub secs: u16,
+}
+
+fn default_balancing_secs() -> u16 {
+    10
+}
+
+/// Part of the configuration a problem was found in, subnets, ranges,
+/// hosts and classes are given by their index
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    Listen,
+    ServerIdentifier,
+    LoadBalancing,
+    Failover,
+    Api,
+    Range(usize, usize),
+    Host(usize, usize),
+    /// Field of the lease times of a subnet
+    SubnetLease(usize, &'static str),
+    /// Field of the lease times of a host
+    HostLease(usize, usize, &'static str),
+    SubnetOption(usize, String),
+    ClassOption(usize, String),
+}
+
+/// Inconsistency found by `Configuration::validate`
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub location: Location,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Static configuration for a single client, identified by its hardware address
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Host {
+    pub hardware_address: String,
+    pub fixed_address: Option<Ipv4Addr>,
+    pub lease: Option<LeaseTime>,
+}
+
+/// Probe an address before offering it, see RFC 2131 section 2.2
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ConflictDetection {
+    pub enabled: bool,
+    /// Time to wait for an answer, in milliseconds
+    pub timeout: u64,
+    /// Time spent probing for a single DHCPDISCOVER, in milliseconds. The
+    /// server loop answers nothing else meanwhile, once it is over the
+    /// DHCPDISCOVER is dropped and the client sends it again.
+    #[serde(default = "default_probe_budget")]
+    pub budget: u64,
+    /// Time an address answering a probe or declined by a client is kept
+    /// out of the pool, in seconds
+    #[serde(default = "default_abandon_time")]
+    pub abandon_time: u64,
+}
+
+fn default_probe_budget() -> u64 {
+    1000
+}
+
+// One day, as ISC dhcpd's abandon-lease-time
+fn default_abandon_time() -> u64 {
+    86400
 }
 
 impl AddressRange {
-    pub fn new(start_address: Ipv4Addr, end_address: Ipv4Addr, subnet_mask: Ipv4Addr) -> Self {
+    pub fn new(start_address: Ipv4Addr, end_address: Ipv4Addr) -> Self {
         AddressRange {
             start_address,
             end_address,
-            subnet_mask,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            prefer: Vec::new(),
+        }
+    }
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        self.start_address <= addr && addr <= self.end_address
+    }
+    pub fn allows(&self, classes: &[String]) -> bool {
+        allows(&self.allow, &self.deny, classes)
+    }
+    pub fn prefers(&self, classes: &[String]) -> bool {
+        classes.iter().any(|class| self.prefer.contains(class))
+    }
+}
+
+fn allows(allow: &[String], deny: &[String], classes: &[String]) -> bool {
+    if classes.iter().any(|class| deny.contains(class)) {
+        return false;
+    }
+    allow.is_empty() || classes.iter().any(|class| allow.contains(class))
+}
+
+impl OptionValue {
+    /// Encode the value for an option, numbers take the size the option expects
+    pub fn to_subfield(&self, op: options::Option) -> Result<OptionSubfield, String> {
+        if let Some(expected) = expected_kind(op) {
+            let matches = match self {
+                OptionValue::Flag(_) => expected == "a boolean",
+                OptionValue::Number(_) => expected == "a number",
+                OptionValue::Address(_) => {
+                    expected == "an address" || expected == "a list of addresses"
+                }
+                OptionValue::Addresses(_) => expected == "a list of addresses",
+                OptionValue::Text(_) => expected == "a string",
+            };
+            if !matches {
+                return Err(format!("{:?} expects {}", op, expected));
+            }
+        }
+        match self {
+            OptionValue::Flag(flag) => OptionSubfield::new(op, vec![u8::from(*flag)]),
+            OptionValue::Number(number) => {
+                let bytes = number.to_be_bytes();
+                let mut errors = Vec::new();
+                for size in [4, 2, 1] {
+                    if bytes[..4 - size].iter().any(|&byte| byte != 0) {
+                        continue;
+                    }
+                    match OptionSubfield::new(op, bytes[4 - size..].to_vec()) {
+                        Ok(subfield) => return Ok(subfield),
+                        Err(e) => errors.push(e),
+                    }
+                }
+                Err(errors.pop().unwrap_or(format!("{} is too big for {:?}", number, op)))
+            }
+            OptionValue::Address(addr) => OptionSubfield::new(op, addr.octets().to_vec()),
+            OptionValue::Addresses(addrs) => OptionSubfield::new(
+                op,
+                addrs.iter().flat_map(|addr| addr.octets()).collect(),
+            ),
+            OptionValue::Text(text) => OptionSubfield::new(op, text.as_bytes().to_vec()),
+        }
+    }
+}
+
+/// Kind of value an option takes in the configuration, None for the ones
+/// accepting anything of the right length
+fn expected_kind(op: options::Option) -> Option<&'static str> {
+    use options::Option::*;
+    let kind = match op {
+        SubnetMask | SwapServer | BroadcastAddress | RouterSolicitationAddress => "an address",
+        Router
+        | TimeServer
+        | NameServer
+        | DomainNameServer
+        | LogServer
+        | CookieServer
+        | LPR
+        | ImpressServer
+        | ResourceLocationServer
+        | NetworkInformationServers
+        | NetworkTimeProtocolServers
+        | NetBIOSOverTCPIPNameServer
+        | NetBIOSOverTCPIPDatagramDistributionServer
+        | XWindowSystemFontServer
+        | XWindowSystemDisplayManager => "a list of addresses",
+        TimeOffset
+        | BootFileSize
+        | MaximumDatagramReassemblySize
+        | DefaultIpTTL
+        | PathMTUAgingTimeout
+        | InterfaceMTU
+        | ARPCacheTimeout
+        | TCPDefaultTTL
+        | TCPKeepaliveInterval
+        | NetBIOSOverTCPIPNodeType => "a number",
+        IPForwarding
+        | NonLocalSourceRouting
+        | AllSubnetsAreLocal
+        | PerformMaskDiscovery
+        | MaskSupplier
+        | PerformRouterDiscovery
+        | TrailerEncapsulation
+        | EthernetEncapsulation
+        | TCPKeepaliveGarbage => "a boolean",
+        HostName
+        | MeritDumpFile
+        | DomainName
+        | RootPath
+        | ExtensionsPath
+        | NetworkInformationServiceDomain
+        | NetBIOSOverTCPIPScope
+        | TFTPServerName
+        | BootfileName => "a string",
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// Encode options from the configuration, keyed by their dhcpd.conf(5) name
+pub fn encode_options(
+    options: &BTreeMap<String, OptionValue>,
+) -> Result<Vec<OptionSubfield>, String> {
+    options
+        .iter()
+        .map(|(name, value)| encode_option(name, value))
+        .collect()
+}
+
+fn encode_option(name: &str, value: &OptionValue) -> Result<OptionSubfield, String> {
+    let op = options::Option::from_name(name).ok_or_else(|| format!("unknown option {}", name))?;
+    value
+        .to_subfield(op)
+        .map_err(|e| format!("invalid value for option {} : {}", name, e))
+}
+
+impl ClassMatch {
+    pub fn matches(&self, msg: &Message) -> bool {
+        if let Some(prefix) = &self.vendor_class {
+            match msg.option(options::Option::ClassIdentifier) {
+                Some(op) if op.data.starts_with(prefix.as_bytes()) => {}
+                _ => return false,
+            }
+        }
+        if let Some(user_class) = &self.user_class {
+            if !user_classes(msg).contains(&user_class.as_bytes()) {
+                return false;
+            }
+        }
+        if let Some(oui) = &self.oui {
+            if !msg.mac_address().starts_with(&oui.to_ascii_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(circuit_id) = &self.circuit_id {
+            if relay_agent_suboption(msg, 1) != Some(circuit_id.as_bytes()) {
+                return false;
+            }
+        }
+        if let Some(remote_id) = &self.remote_id {
+            if relay_agent_suboption(msg, 2) != Some(remote_id.as_bytes()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// User classes of option 77. RFC 3004 defines a list of length prefixed
+/// classes but many clients (iPXE among them) send a plain string, so the
+/// whole option is also returned as a class.
+pub fn user_classes(msg: &Message) -> Vec<&[u8]> {
+    let data: &[u8] = match msg.option(options::Option::UserClass) {
+        Some(op) => &op.data,
+        None => return Vec::new(),
+    };
+    let mut classes: Vec<&[u8]> = vec![data];
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = usize::from(data[offset]);
+        if len == 0 || offset + 1 + len > data.len() {
+            break;
+        }
+        classes.push(&data[offset + 1..offset + 1 + len]);
+        offset += 1 + len;
+    }
+    classes
+}
+
+/// Sub-option of the relay agent information option, RFC 3046
+fn relay_agent_suboption(msg: &Message, code: u8) -> Option<&[u8]> {
+    let data: &[u8] = &msg.option(options::Option::RelayAgentInformation)?.data;
+    let mut offset = 0;
+    while offset + 1 < data.len() {
+        let len = usize::from(data[offset + 1]);
+        let value = data.get(offset + 2..offset + 2 + len)?;
+        if data[offset] == code {
+            return Some(value);
+        }
+        offset += 2 + len;
+    }
+    None
+}
+
+impl Subnet {
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask: u32 = self.subnet_mask.into();
+        u32::from(addr) & mask == u32::from(self.network) & mask
+    }
+    pub fn host(&self, mac: &str) -> Option<&Host> {
+        self.hosts
+            .iter()
+            .find(|host| host.hardware_address.eq_ignore_ascii_case(mac))
+    }
+    pub fn allows(&self, classes: &[String]) -> bool {
+        allows(&self.allow, &self.deny, classes)
+    }
+    pub fn domain_name(&self) -> Option<&str> {
+        match self.options.get("domain-name") {
+            Some(OptionValue::Text(domain)) => Some(domain.as_str()),
+            _ => None,
+        }
+    }
+    /// Lease settings for a client, a host entry takes precedence over the subnet
+    pub fn lease_for(&self, mac: &str) -> &LeaseTime {
+        match self.host(mac).and_then(|host| host.lease.as_ref()) {
+            Some(lease) => lease,
+            None => &self.lease,
+        }
+    }
+}
+
+impl Boot {
+    pub fn filename(&self, architecture: Option<Architecture>, ipxe: bool) -> Option<&str> {
+        if ipxe && self.ipxe_script.is_some() {
+            return self.ipxe_script.as_deref();
+        }
+        let filename = match architecture {
+            Some(Architecture::Bios) => &self.bios,
+            Some(Architecture::UefiIa32) => &self.uefi_ia32,
+            Some(Architecture::UefiX64) => &self.uefi_x64,
+            Some(Architecture::UefiArm32) => &self.uefi_arm32,
+            Some(Architecture::UefiArm64) => &self.uefi_arm64,
+            None => &None,
+        };
+        filename.as_ref().or(self.filename.as_ref()).map(|f| f.as_str())
+    }
+}
+
+impl LeaseTime {
+    /// Lease to give, honouring the client request (option 51) within bounds
+    pub fn negotiate(&self, requested: Option<Duration>) -> Duration {
+        match requested {
+            Some(requested) => requested.clamp(
+                Duration::from_secs(self.min),
+                Duration::from_secs(self.max.max(self.min)),
+            ),
+            None => Duration::from_secs(self.default),
+        }
+    }
+    /// T1, option 58
+    pub fn renewal_time(&self, lease: Duration) -> Duration {
+        fraction_of(lease, self.renewal).unwrap_or_else(|| lease.mul_f64(DEFAULT_RENEWAL))
+    }
+    /// T2, option 59
+    pub fn rebinding_time(&self, lease: Duration) -> Duration {
+        fraction_of(lease, self.rebinding).unwrap_or_else(|| lease.mul_f64(DEFAULT_REBINDING))
+    }
+    /// Inconsistent values, with the name of the field they are reported on
+    pub fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let fraction = |value: f64| value > 0.0 && value < 1.0;
+        if !fraction(self.renewal) {
+            problems.push(("renewal", format!("renewal {} should be between 0 and 1", self.renewal)));
+        }
+        if !fraction(self.rebinding) {
+            problems.push((
+                "rebinding",
+                format!("rebinding {} should be between 0 and 1", self.rebinding),
+            ));
+        } else if fraction(self.renewal) && self.renewal >= self.rebinding {
+            problems.push((
+                "rebinding",
+                format!(
+                    "rebinding {} should be greater than renewal {}",
+                    self.rebinding, self.renewal
+                ),
+            ));
+        }
+        if self.default < self.min || self.default > self.max {
+            problems.push((
+                "default",
+                format!(
+                    "default lease {} should be between min {} and max {}",
+                    self.default, self.min, self.max
+                ),
+            ));
+        } else if self.min > self.max {
+            problems.push(("min", format!("min lease {} is greater than max {}", self.min, self.max)));
+        }
+        problems
+    }
+}
+
+/// `fraction` of a lease, None when the fraction is not between 0 and 1
+fn fraction_of(lease: Duration, fraction: f64) -> Option<Duration> {
+    if !(fraction > 0.0 && fraction < 1.0) {
+        return None;
+    }
+    Duration::try_from_secs_f64(lease.as_secs_f64() * fraction).ok()
+}
+
+impl Default for LeaseTime {
+    fn default() -> Self {
+        LeaseTime {
+            default: DEFAULT_LEASE,
+            min: DEFAULT_MIN_LEASE,
+            max: DEFAULT_MAX_LEASE,
+            renewal: DEFAULT_RENEWAL,
+            rebinding: DEFAULT_REBINDING,
+        }
+    }
+}
+
+impl Default for ConflictDetection {
+    fn default() -> Self {
+        ConflictDetection {
+            enabled: false,
+            timeout: 500,
+            budget: default_probe_budget(),
+            abandon_time: default_abandon_time(),
         }
     }
 }
@@ -30,23 +699,257 @@ impl AddressRange {
 impl Default for Configuration {
     fn default() -> Self {
         Configuration {
-            range: AddressRange {
-                start_address: [192, 0, 0, 1].into(),
-                end_address: [192, 0, 0, 100].into(),
+            listen: default_listen(),
+            server_identifier: None,
+            subnets: vec![Subnet {
+                network: [192, 0, 0, 0].into(),
                 subnet_mask: [255, 255, 255, 0].into(),
-            },
+                ranges: vec![AddressRange::new(
+                    [192, 0, 0, 1].into(),
+                    [192, 0, 0, 100].into(),
+                )],
+                lease: LeaseTime::default(),
+                hosts: Vec::new(),
+                options: BTreeMap::new(),
+                allow: Vec::new(),
+                deny: Vec::new(),
+                boot: None,
+                append_domain: false,
+            }],
+            classes: Vec::new(),
+            conflict_detection: ConflictDetection::default(),
+            tftp: None,
+            ddns: None,
+            leases_file: None,
+            failover: None,
+            load_balancing: None,
+            api: None,
+            metrics: None,
+            capture: None,
             configuration_path: String::new(),
         }
     }
 }
 impl Configuration {
-    pub fn new(range: AddressRange, configuration_path: String) -> Configuration {
+    pub fn new(subnets: Vec<Subnet>, configuration_path: String) -> Configuration {
         Configuration {
-            range,
+            listen: default_listen(),
+            server_identifier: None,
+            subnets,
+            classes: Vec::new(),
+            conflict_detection: ConflictDetection::default(),
+            tftp: None,
+            ddns: None,
+            leases_file: None,
+            failover: None,
+            load_balancing: None,
+            api: None,
+            metrics: None,
+            capture: None,
             configuration_path,
         }
     }
-    pub fn read(&self) {
-        // todo read conf file
+    pub fn read(configuration_path: String) -> Result<Configuration, String> {
+        let content = fs::read_to_string(&configuration_path)
+            .map_err(|e| format!("unable to read {} : {}", configuration_path, e))?;
+        let mut configuration: Configuration = toml::from_str(&content)
+            .map_err(|e| format!("unable to parse {} : {}", configuration_path, e))?;
+        configuration.configuration_path = configuration_path;
+        Ok(configuration)
+    }
+    /// Problems the parser does not catch, an empty list when the
+    /// configuration can be served
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = Vec::new();
+        let mut problem = |location: Location, message: String| {
+            problems.push(Problem { location, message })
+        };
+        // The clients send their requests to the server identifier
+        match self.server_identifier {
+            Some(identifier) if identifier.is_unspecified() => problem(
+                Location::ServerIdentifier,
+                format!("server identifier {} can't be reached by the clients", identifier),
+            ),
+            None if self.listen.ip().is_unspecified() => problem(
+                Location::Listen,
+                format!("server_identifier is required when listening on {}", self.listen),
+            ),
+            _ => {}
+        }
+        if let Some(failover) = &self.failover {
+            if failover.secret.is_empty() {
+                problem(Location::Failover, "failover secret is empty".to_string());
+            }
+        }
+        if let Some(api) = &self.api {
+            if api.token.is_empty() {
+                problem(Location::Api, "API token is empty".to_string());
+            }
+        }
+        if let Some(balancing) = &self.load_balancing {
+            if balancing.index >= balancing.servers {
+                problem(
+                    Location::LoadBalancing,
+                    format!(
+                        "load balancing index {} should be lower than the number of servers {}",
+                        balancing.index, balancing.servers
+                    ),
+                );
+            }
+        }
+        let ranges: Vec<(usize, usize, &AddressRange)> = self
+            .subnets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, subnet)| {
+                subnet
+                    .ranges
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, range)| (i, j, range))
+            })
+            .collect();
+        let mut macs: Vec<String> = Vec::new();
+        for (i, subnet) in self.subnets.iter().enumerate() {
+            for (name, value) in subnet.options.iter() {
+                if let Err(e) = encode_option(name, value) {
+                    problem(
+                        Location::SubnetOption(i, name.clone()),
+                        format!("subnet {} : {}", subnet.network, e),
+                    );
+                }
+            }
+            for (field, message) in subnet.lease.problems() {
+                problem(
+                    Location::SubnetLease(i, field),
+                    format!("subnet {} : {}", subnet.network, message),
+                );
+            }
+            for (j, range) in subnet.ranges.iter().enumerate() {
+                let name = format!("{}-{}", range.start_address, range.end_address);
+                if range.start_address > range.end_address {
+                    problem(
+                        Location::Range(i, j),
+                        format!("subnet {} : range {} ends before it starts", subnet.network, name),
+                    );
+                }
+                if !subnet.contains(range.start_address) || !subnet.contains(range.end_address) {
+                    problem(
+                        Location::Range(i, j),
+                        format!(
+                            "subnet {} : range {} is outside of the subnet mask {}",
+                            subnet.network, name, subnet.subnet_mask
+                        ),
+                    );
+                }
+                // Each overlap is reported once, on the second range
+                for (_, _, other) in ranges.iter().take_while(|(k, l, _)| (*k, *l) < (i, j)) {
+                    if range.start_address <= other.end_address
+                        && other.start_address <= range.end_address
+                    {
+                        problem(
+                            Location::Range(i, j),
+                            format!(
+                                "subnet {} : range {} overlaps range {}-{}",
+                                subnet.network, name, other.start_address, other.end_address
+                            ),
+                        );
+                    }
+                }
+            }
+            for (j, host) in subnet.hosts.iter().enumerate() {
+                let mac = host.hardware_address.to_ascii_lowercase();
+                if macs.contains(&mac) {
+                    problem(
+                        Location::Host(i, j),
+                        format!("hardware address {} has several reservations", mac),
+                    );
+                }
+                for (field, message) in host.lease.iter().flat_map(LeaseTime::problems) {
+                    problem(
+                        Location::HostLease(i, j, field),
+                        format!("host {} : {}", mac, message),
+                    );
+                }
+                macs.push(mac);
+                let addr = match host.fixed_address {
+                    Some(addr) => addr,
+                    None => continue,
+                };
+                if !subnet.contains(addr) {
+                    problem(
+                        Location::Host(i, j),
+                        format!(
+                            "subnet {} : fixed address {} is outside of the subnet",
+                            subnet.network, addr
+                        ),
+                    );
+                }
+                if let Some((_, _, range)) = ranges.iter().find(|(_, _, range)| range.contains(addr)) {
+                    problem(
+                        Location::Host(i, j),
+                        format!(
+                            "subnet {} : fixed address {} is inside the dynamic range {}-{}",
+                            subnet.network, addr, range.start_address, range.end_address
+                        ),
+                    );
+                }
+            }
+        }
+        for (i, class) in self.classes.iter().enumerate() {
+            for (name, value) in class.options.iter() {
+                if let Err(e) = encode_option(name, value) {
+                    problem(
+                        Location::ClassOption(i, name.clone()),
+                        format!("class {} : {}", class.name, e),
+                    );
+                }
+            }
+        }
+        problems
+    }
+    /// Whether a client may keep an address : it belongs to one of the
+    /// ranges of its subnet or is the fixed address of the client
+    pub fn is_valid_lease(&self, mac: &str, addr: Ipv4Addr) -> bool {
+        self.subnets
+            .iter()
+            .filter(|subnet| subnet.contains(addr))
+            .any(|subnet| {
+                subnet.ranges.iter().any(|range| range.contains(addr))
+                    || subnet
+                        .host(mac)
+                        .is_some_and(|host| host.fixed_address == Some(addr))
+            })
+    }
+    /// Names of the classes a client belongs to, in configuration order
+    pub fn classify(&self, msg: &Message) -> Vec<String> {
+        self.classes
+            .iter()
+            .filter(|class| class.matcher.matches(msg))
+            .map(|class| class.name.clone())
+            .collect()
+    }
+    /// Options of the subnet, overridden by the ones of the client classes
+    pub fn options_for(
+        &self,
+        subnet: usize,
+        classes: &[String],
+    ) -> Result<Vec<OptionSubfield>, String> {
+        let mut result: Vec<OptionSubfield> = encode_options(&self.subnets[subnet].options)?;
+        for class in self.classes.iter().filter(|c| classes.contains(&c.name)) {
+            for option in encode_options(&class.options)? {
+                result.retain(|e| e.op_code != option.op_code);
+                result.push(option);
+            }
+        }
+        Ok(result)
+    }
+    /// Subnet serving a request : the one containing the relay address, or the
+    /// first one for directly connected clients
+    pub fn subnet_for(&self, giaddr: Ipv4Addr) -> Option<usize> {
+        if giaddr.is_unspecified() {
+            return if self.subnets.is_empty() { None } else { Some(0) };
+        }
+        self.subnets.iter().position(|subnet| subnet.contains(giaddr))
     }
 }
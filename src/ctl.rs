@@ -0,0 +1,194 @@
+//! Client side of the management API, for the subcommands of the command
+//! line. Requests are plain HTTP/1.0 so the answer is never chunked.
+
+use chrono::{Local, TimeZone};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::check;
+use crate::cli::{Args, Command};
+use crate::configuration::Configuration;
+
+// Connection, read and write timeout of the requests
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct ApiClient {
+    address: SocketAddr,
+    token: String,
+}
+
+pub fn run(args: &Args, command: &Command) -> Result<(), String> {
+    match command {
+        Command::Validate => {
+            let path = args.configuration_path("to validate the configuration")?;
+            let problems = check::check(path);
+            if args.json {
+                println!(
+                    "{}",
+                    serde_json::json!({ "valid": problems.is_empty(), "problems": problems })
+                );
+            } else if problems.is_empty() {
+                println!("{} is valid", path);
+            } else {
+                for problem in problems.iter() {
+                    println!("{}", problem);
+                }
+            }
+            match problems.len() {
+                0 => Ok(()),
+                1 => Err("1 problem found".to_string()),
+                count => Err(format!("{} problems found", count)),
+            }
+        }
+        Command::Leases { mac, hostname } => {
+            let mut query: Vec<String> = Vec::new();
+            if let Some(mac) = mac {
+                query.push(format!("mac={}", percent_encode(mac)));
+            }
+            if let Some(hostname) = hostname {
+                query.push(format!("hostname={}", percent_encode(hostname)));
+            }
+            let leases = ApiClient::new(args)?.request("GET", &format!("/leases?{}", query.join("&")))?;
+            output(args, &leases, &["address", "hardware_address", "hostname", "state", "expire"]);
+            Ok(())
+        }
+        Command::History { mac } => {
+            let history = ApiClient::new(args)?.request("GET", &format!("/history/{}", percent_encode(mac)))?;
+            output(args, &history, &["time", "event", "address"]);
+            Ok(())
+        }
+        Command::Release { address } => {
+            let released = ApiClient::new(args)?.request("DELETE", &format!("/leases/{}", address))?;
+            if args.json {
+                println!("{}", released);
+            } else {
+                println!("{} released", address);
+            }
+            Ok(())
+        }
+        Command::PartnerDown => {
+            let state = ApiClient::new(args)?.request("POST", "/failover/partner-down")?;
+            if args.json {
+                println!("{}", state);
+            } else {
+                println!("failover peer down");
+            }
+            Ok(())
+        }
+    }
+}
+
+impl ApiClient {
+    /// Address and token given on the command line, or else read from the
+    /// configuration file
+    fn new(args: &Args) -> Result<ApiClient, String> {
+        if let (Some(address), Some(token)) = (args.api, &args.token) {
+            return Ok(ApiClient {
+                address,
+                token: token.clone(),
+            });
+        }
+        let path = args.configuration_path("unless --api and --token are given")?;
+        let configuration = Configuration::read(path.to_string())?;
+        let api = configuration
+            .api
+            .ok_or("the management API is not enabled in the configuration")?;
+        Ok(ApiClient {
+            address: args.api.unwrap_or(api.address),
+            token: args.token.clone().unwrap_or(api.token),
+        })
+    }
+    fn request(&self, method: &str, path: &str) -> Result<Value, String> {
+        let error = |e: std::io::Error| format!("unable to reach the server on {} : {}", self.address, e);
+        let mut stream = TcpStream::connect_timeout(&self.address, TIMEOUT).map_err(error)?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(error)?;
+        let request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\n\r\n",
+            method, path, self.address, self.token
+        );
+        stream.write_all(request.as_bytes()).map_err(error)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(error)?;
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or("malformed answer from the server")?;
+        let status: u16 = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or("malformed answer from the server")?;
+        let document: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        if status != 200 {
+            let message = document["error"].as_str().unwrap_or("unknown error");
+            return Err(format!("server answered {} : {}", status, message));
+        }
+        Ok(document)
+    }
+}
+
+/// Print a list of objects as JSON or as a table of the given columns
+fn output(args: &Args, document: &Value, columns: &[&str]) {
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(document).unwrap_or_default());
+        return;
+    }
+    let rows: Vec<Vec<String>> = document
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .map(|item| columns.iter().map(|column| cell(column, &item[column])).collect())
+                .collect()
+        })
+        .unwrap_or_default();
+    let headers: Vec<String> = columns
+        .iter()
+        .map(|column| column.replace('_', " ").to_uppercase())
+        .collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain([headers[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in [headers].iter().chain(rows.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Timestamps are shown in local time
+fn cell(column: &str, value: &Value) -> String {
+    match (column, value) {
+        ("expire" | "time", Value::Number(time)) => time
+            .as_i64()
+            .and_then(|time| Local.timestamp_opt(time, 0).single())
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| time.to_string()),
+        (_, Value::String(value)) => value.clone(),
+        (_, Value::Null) => "-".to_string(),
+        (_, value) => value.to_string(),
+    }
+}
+
+fn percent_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b':' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
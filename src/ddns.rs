@@ -0,0 +1,553 @@
+//! Dynamic DNS updates, RFC 2136, optionally signed with TSIG (RFC 8945).
+//!
+//! Names are shared with the other clients as RFC 4703 describes : a DHCID
+//! record (RFC 4701) is added next to the A record of a client, and an
+//! existing A record is only replaced or deleted when the DHCID of the name
+//! is the one of the client. A name used by another client, or added without
+//! a DHCID, is left in place and the update fails. The PTR records of the
+//! leased addresses belong to the server, they are replaced without
+//! prerequisite once the A record is in place.
+//!
+//! Updates are queued to a worker thread so a slow DNS server doesn't block
+//! the DHCP exchanges, and sent one at a time in order, so a removal never
+//! overtakes the registration following it.
+
+use base64::Engine;
+use hmac::digest::core_api::BlockSizeUser;
+use hmac::digest::Digest;
+use hmac::{Mac, SimpleHmac};
+use rand::Rng;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+use crate::api::constant_time_eq;
+use crate::configuration::{Ddns, TsigKey};
+use crate::fqdn::Updates;
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SOA: u16 = 6;
+const TYPE_DHCID: u16 = 49;
+const TYPE_TSIG: u16 = 250;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TSIG_FUDGE: u16 = 300;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const RCODE_NOERROR: u8 = 0;
+const RCODE_YXDOMAIN: u8 = 6;
+const RCODE_NXRRSET: u8 = 8;
+// DHCID identifier type of a hardware address and digest type of SHA-256,
+// RFC 4701 section 3.3
+const DHCID_HARDWARE_ADDRESS: u16 = 0;
+const DHCID_SHA256: u8 = 1;
+const HTYPE_ETHERNET: u8 = 1;
+// TSIG errors, RFC 8945 section 3
+const TSIG_BADSIG: u16 = 16;
+const TSIG_BADKEY: u16 = 17;
+const TSIG_BADTIME: u16 = 18;
+
+#[derive(Clone)]
+pub struct DnsUpdater {
+    configuration: Ddns,
+    /// Changes waiting for the worker thread, None for the worker itself
+    queue: Option<Sender<Change>>,
+}
+
+/// Records of a client to update : its hardware address, hostname and address
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Add the A, DHCID and PTR records, replacing the ones of the client
+    Register(String, String, Ipv4Addr, Updates),
+    /// Remove the records added by `Register`
+    Remove(String, String, Ipv4Addr, Updates),
+}
+
+/// Resource record of the update section
+struct Record {
+    name: String,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+impl DnsUpdater {
+    pub fn new(configuration: Ddns) -> Self {
+        let (queue, changes) = mpsc::channel::<Change>();
+        let worker = DnsUpdater {
+            configuration: configuration.clone(),
+            queue: None,
+        };
+        thread::spawn(move || {
+            for change in changes {
+                if let Err(e) = worker.apply(&change) {
+                    error!(?change, error = %e, "DNS update failed");
+                }
+            }
+        });
+        DnsUpdater {
+            configuration,
+            queue: Some(queue),
+        }
+    }
+    /// Fully qualified name of a client in the forward zone
+    pub fn fqdn(&self, hostname: &str) -> String {
+        let zone = self.configuration.zone.trim_end_matches('.');
+        let hostname = hostname.trim_end_matches('.');
+        if hostname == zone || hostname.ends_with(&format!(".{}", zone)) {
+            format!("{}.", hostname)
+        } else {
+            format!("{}.{}.", hostname, zone)
+        }
+    }
+    /// Let clients update their own A record when they ask to (option 81)
+    pub fn client_updates(&self) -> bool {
+        self.configuration.client_updates
+    }
+    /// Add the A, DHCID and PTR records of a client, replacing its previous ones
+    pub fn register(&self, mac: &str, hostname: &str, address: Ipv4Addr, updates: Updates) {
+        self.queue(Change::Register(
+            mac.to_string(),
+            hostname.to_string(),
+            address,
+            updates,
+        ));
+    }
+    /// Remove the records added by `register`
+    pub fn remove(&self, mac: &str, hostname: &str, address: Ipv4Addr, updates: Updates) {
+        self.queue(Change::Remove(
+            mac.to_string(),
+            hostname.to_string(),
+            address,
+            updates,
+        ));
+    }
+    fn queue(&self, change: Change) {
+        let queued = self.queue.as_ref().map(|queue| queue.send(change));
+        if !matches!(queued, Some(Ok(()))) {
+            error!("DNS update worker stopped");
+        }
+    }
+    /// Send the updates of a change to the forward then the reverse zone,
+    /// waiting for the answers of the server
+    pub fn apply(&self, change: &Change) -> Result<(), String> {
+        let (Change::Register(mac, hostname, address, updates)
+        | Change::Remove(mac, hostname, address, updates)) = change;
+        let fqdn = self.fqdn(hostname);
+        let reverse = reverse_name(*address);
+        let mut errors = Vec::new();
+        if updates.forward {
+            let forward = match change {
+                Change::Register(..) => self.register_name(mac, &fqdn, *address),
+                Change::Remove(..) => self.remove_name(mac, &fqdn, *address),
+            };
+            if let Err(e) = forward {
+                errors.push(format!("{} : {}", fqdn, e));
+            }
+        }
+        // A PTR record never points to a name the client couldn't register
+        let named = errors.is_empty() || matches!(change, Change::Remove(..));
+        if updates.reverse && named {
+            let mut backward = vec![Record::delete_rrset(&reverse, TYPE_PTR)];
+            if let Change::Register(..) = change {
+                let ttl = self.configuration.ttl;
+                backward.push(Record::new(&reverse, TYPE_PTR, CLASS_IN, ttl, encode_name(&fqdn)));
+            }
+            let result = self
+                .update(&self.reverse_zone(*address), &[], &backward)
+                .and_then(accepted);
+            if let Err(e) = result {
+                errors.push(format!("{} : {}", reverse, e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+    /// Add the A and DHCID records of a client, RFC 4703 section 5.3.1 : the
+    /// name must not exist, or its DHCID must be the one of the client for
+    /// its A record to be replaced
+    fn register_name(&self, mac: &str, fqdn: &str, address: Ipv4Addr) -> Result<(), String> {
+        let ttl = self.configuration.ttl;
+        let zone = &self.configuration.zone;
+        let dhcid = dhcid(mac, fqdn);
+        let a = Record::new(fqdn, TYPE_A, CLASS_IN, ttl, address.octets().to_vec());
+        let owner = Record::new(fqdn, TYPE_DHCID, CLASS_IN, ttl, dhcid.clone());
+        match self.update(zone, &[Record::not_in_use(fqdn)], &[a, owner])? {
+            RCODE_YXDOMAIN => {}
+            rcode => return accepted(rcode),
+        }
+        let owned = Record::new(fqdn, TYPE_DHCID, CLASS_IN, 0, dhcid);
+        let a = Record::new(fqdn, TYPE_A, CLASS_IN, ttl, address.octets().to_vec());
+        match self.update(zone, &[owned], &[Record::delete_rrset(fqdn, TYPE_A), a])? {
+            RCODE_NXRRSET => Err("name in use by another client".to_string()),
+            rcode => accepted(rcode),
+        }
+    }
+    /// Delete the A and DHCID records of a client, RFC 4703 section 5.5, as
+    /// long as the DHCID of the name is still the one of the client
+    fn remove_name(&self, mac: &str, fqdn: &str, address: Ipv4Addr) -> Result<(), String> {
+        let dhcid = dhcid(mac, fqdn);
+        let owned = Record::new(fqdn, TYPE_DHCID, CLASS_IN, 0, dhcid.clone());
+        let records = [
+            Record::new(fqdn, TYPE_A, CLASS_NONE, 0, address.octets().to_vec()),
+            Record::new(fqdn, TYPE_DHCID, CLASS_NONE, 0, dhcid),
+        ];
+        match self.update(&self.configuration.zone, &[owned], &records)? {
+            RCODE_NXRRSET => Err("name in use by another client, left in place".to_string()),
+            rcode => accepted(rcode),
+        }
+    }
+    /// The configured reverse zone, or the /24 one containing the address
+    fn reverse_zone(&self, address: Ipv4Addr) -> String {
+        match &self.configuration.reverse_zone {
+            Some(zone) => zone.clone(),
+            None => {
+                let [a, b, c, _] = address.octets();
+                format!("{}.{}.{}.in-addr.arpa.", c, b, a)
+            }
+        }
+    }
+    /// Send an update message and wait for the answer of the server,
+    /// returns its rcode
+    fn update(
+        &self,
+        zone: &str,
+        prerequisites: &[Record],
+        records: &[Record],
+    ) -> Result<u8, String> {
+        let id: u16 = rand::thread_rng().gen();
+        let mut message = update_message(id, zone, prerequisites, records);
+        let request_mac = match &self.configuration.tsig {
+            Some(key) => Some(sign(&mut message, id, key)?),
+            None => None,
+        };
+
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        socket
+            .send_to(&message, self.configuration.server)
+            .map_err(|e| e.to_string())?;
+        let mut buffer = [0u8; 512];
+        loop {
+            let (num_byte, src_addr) = socket.recv_from(&mut buffer).map_err(|e| e.to_string())?;
+            if src_addr != self.configuration.server || num_byte < 12 {
+                continue;
+            }
+            if u16::from_be_bytes([buffer[0], buffer[1]]) != id {
+                continue;
+            }
+            let response = &buffer[..num_byte];
+            if let (Some(key), Some(request_mac)) = (&self.configuration.tsig, &request_mac) {
+                verify(response, request_mac, key)?;
+            }
+            return Ok(response[3] & 0x0f);
+        }
+    }
+}
+
+impl Record {
+    fn new(name: &str, rtype: u16, class: u16, ttl: u32, rdata: Vec<u8>) -> Self {
+        Record {
+            name: name.to_string(),
+            rtype,
+            class,
+            ttl,
+            rdata,
+        }
+    }
+    /// Delete every record of a type for a name, RFC 2136 section 2.5.2
+    fn delete_rrset(name: &str, rtype: u16) -> Self {
+        Record::new(name, rtype, CLASS_ANY, 0, Vec::new())
+    }
+    /// Prerequisite that a name has no record, RFC 2136 section 2.4.5
+    fn not_in_use(name: &str) -> Self {
+        Record::new(name, TYPE_ANY, CLASS_NONE, 0, Vec::new())
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode_name(&self.name);
+        bytes.extend_from_slice(&self.rtype.to_be_bytes());
+        bytes.extend_from_slice(&self.class.to_be_bytes());
+        bytes.extend_from_slice(&self.ttl.to_be_bytes());
+        bytes.extend_from_slice(&(self.rdata.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.rdata);
+        bytes
+    }
+}
+
+fn update_message(id: u16, zone: &str, prerequisites: &[Record], records: &[Record]) -> Vec<u8> {
+    let mut message: Vec<u8> = id.to_be_bytes().to_vec();
+    message.extend_from_slice(&(OPCODE_UPDATE << 11).to_be_bytes());
+    message.extend_from_slice(&1u16.to_be_bytes()); // ZOCOUNT
+    message.extend_from_slice(&(prerequisites.len() as u16).to_be_bytes()); // PRCOUNT
+    message.extend_from_slice(&(records.len() as u16).to_be_bytes()); // UPCOUNT
+    message.extend_from_slice(&0u16.to_be_bytes()); // ADCOUNT
+    message.extend(encode_name(zone));
+    message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    for record in prerequisites.iter().chain(records.iter()) {
+        message.extend(record.to_bytes());
+    }
+    message
+}
+
+/// Append a TSIG record to a message, RFC 8945 section 4.3. The MAC is
+/// returned, it is part of the signature of the response.
+fn sign(message: &mut Vec<u8>, id: u16, key: &TsigKey) -> Result<Vec<u8>, String> {
+    let algorithm = tsig_algorithm_name(&key.algorithm)?;
+    let time_signed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let time_signed = &time_signed.to_be_bytes()[2..];
+
+    let mut signed = message.clone();
+    signed.extend(tsig_variables(key, time_signed, TSIG_FUDGE, 0, &[])?);
+    let mac = tsig_mac(key, &signed)?;
+
+    let mut rdata = encode_name(algorithm);
+    rdata.extend_from_slice(time_signed);
+    rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(&mac);
+    rdata.extend_from_slice(&id.to_be_bytes()); // original id
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // error
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // other len
+    let record = Record::new(&key.name, TYPE_TSIG, CLASS_ANY, 0, rdata);
+    message.extend(record.to_bytes());
+
+    // One more record in the additional section
+    let additional = u16::from_be_bytes([message[10], message[11]]) + 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    Ok(mac)
+}
+
+/**
+ * Check the TSIG record of a response, RFC 8945 section 5.3. The MAC covers
+ * the MAC of the request, the response without its TSIG record and with
+ * its original ID, then the TSIG variables.
+ */
+fn verify(response: &[u8], request_mac: &[u8], key: &TsigKey) -> Result<(), String> {
+    let malformed = || "malformed TSIG record in the response".to_string();
+    if response[10..12] == [0, 0] {
+        return Err("unsigned response".to_string());
+    }
+    let tsig = last_record(response).ok_or_else(malformed)?;
+    if tsig.rtype != TYPE_TSIG {
+        return Err("unsigned response".to_string());
+    }
+    let rdata = &response[tsig.rdata.clone()];
+    let algorithm_len = name_length(rdata, 0).ok_or_else(malformed)?;
+    let fields = &rdata[algorithm_len..];
+    let field = |offset: usize| -> Result<u16, String> {
+        let bytes = fields.get(offset..offset + 2).ok_or_else(malformed)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let time_signed = fields.get(..6).ok_or_else(malformed)?;
+    let fudge = field(6)?;
+    let mac_len = usize::from(field(8)?);
+    let mac = fields.get(10..10 + mac_len).ok_or_else(malformed)?;
+    let original_id = field(10 + mac_len)?;
+    let error = field(12 + mac_len)?;
+    let other_len = usize::from(field(14 + mac_len)?);
+    let other = fields
+        .get(16 + mac_len..16 + mac_len + other_len)
+        .ok_or_else(malformed)?;
+    match error {
+        0 => {}
+        TSIG_BADSIG => return Err("server refused the signature : BADSIG".to_string()),
+        TSIG_BADKEY => return Err("server refused the signature : BADKEY".to_string()),
+        TSIG_BADTIME => return Err("server refused the signature : BADTIME".to_string()),
+        error => return Err(format!("server refused the signature : error {}", error)),
+    }
+
+    let mut signed: Vec<u8> = (request_mac.len() as u16).to_be_bytes().to_vec();
+    signed.extend_from_slice(request_mac);
+    let mut unsigned = response[..tsig.start].to_vec();
+    unsigned[..2].copy_from_slice(&original_id.to_be_bytes());
+    let additional = u16::from_be_bytes([unsigned[10], unsigned[11]]).saturating_sub(1);
+    unsigned[10..12].copy_from_slice(&additional.to_be_bytes());
+    signed.extend(unsigned);
+    signed.extend(tsig_variables(key, time_signed, fudge, error, other)?);
+    if !constant_time_eq(&tsig_mac(key, &signed)?, mac) {
+        return Err("invalid signature of the response".to_string());
+    }
+
+    let mut seconds = [0u8; 8];
+    seconds[2..].copy_from_slice(time_signed);
+    let time_signed = u64::from_be_bytes(seconds);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    if now.abs_diff(time_signed) > u64::from(fudge) {
+        return Err("response signed outside of the allowed time window".to_string());
+    }
+    Ok(())
+}
+
+/// TSIG variables of RFC 8945 section 4.3.3, in their canonical form
+fn tsig_variables(
+    key: &TsigKey,
+    time_signed: &[u8],
+    fudge: u16,
+    error: u16,
+    other: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut variables = encode_name(&key.name.to_ascii_lowercase());
+    variables.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    variables.extend_from_slice(&0u32.to_be_bytes()); // TTL
+    variables.extend(encode_name(tsig_algorithm_name(&key.algorithm)?));
+    variables.extend_from_slice(time_signed);
+    variables.extend_from_slice(&fudge.to_be_bytes());
+    variables.extend_from_slice(&error.to_be_bytes());
+    variables.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    variables.extend_from_slice(other);
+    Ok(variables)
+}
+
+fn tsig_mac(key: &TsigKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    let secret = base64::engine::general_purpose::STANDARD
+        .decode(&key.secret)
+        .map_err(|e| format!("invalid TSIG secret : {}", e))?;
+    match key.algorithm.as_str() {
+        "hmac-md5" => Ok(hmac::<md5::Md5>(&secret, data)),
+        "hmac-sha1" => Ok(hmac::<sha1::Sha1>(&secret, data)),
+        "hmac-sha256" => Ok(hmac::<sha2::Sha256>(&secret, data)),
+        algorithm => Err(format!("unsupported TSIG algorithm {}", algorithm)),
+    }
+}
+
+/// Position of a resource record in a message
+struct RecordPosition {
+    start: usize,
+    rtype: u16,
+    rdata: std::ops::Range<usize>,
+}
+
+/// The last record of a message, where the TSIG record is
+fn last_record(message: &[u8]) -> Option<RecordPosition> {
+    let count = |offset: usize| usize::from(u16::from_be_bytes([message[offset], message[offset + 1]]));
+    if message.len() < 12 {
+        return None;
+    }
+    let questions = count(4);
+    let records = count(6) + count(8) + count(10);
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset += name_length(message, offset)? + 4;
+    }
+    let mut last = None;
+    for _ in 0..records {
+        let start = offset;
+        offset += name_length(message, offset)?;
+        let header = message.get(offset..offset + 10)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let rdlength = usize::from(u16::from_be_bytes([header[8], header[9]]));
+        let rdata = offset + 10..offset + 10 + rdlength;
+        message.get(rdata.clone())?;
+        offset = rdata.end;
+        last = Some(RecordPosition { start, rtype, rdata });
+    }
+    last
+}
+
+/// Length of a name in the wire format, a compression pointer ends it
+fn name_length(message: &[u8], start: usize) -> Option<usize> {
+    let mut offset = start;
+    loop {
+        let len = *message.get(offset)?;
+        match len {
+            0 => return Some(offset + 1 - start),
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2 - start),
+            len => offset += 1 + usize::from(len),
+        }
+    }
+}
+
+fn hmac<D: Digest + BlockSizeUser>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = SimpleHmac::<D>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn tsig_algorithm_name(algorithm: &str) -> Result<&'static str, String> {
+    match algorithm {
+        "hmac-md5" => Ok("hmac-md5.sig-alg.reg.int."),
+        "hmac-sha1" => Ok("hmac-sha1."),
+        "hmac-sha256" => Ok("hmac-sha256."),
+        _ => Err(format!("unsupported TSIG algorithm {}", algorithm)),
+    }
+}
+
+/// An update is done when the server answers NOERROR
+fn accepted(rcode: u8) -> Result<(), String> {
+    match rcode {
+        RCODE_NOERROR => Ok(()),
+        rcode => Err(format!("server answered with rcode {}", rcode_name(rcode))),
+    }
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        1 => "FORMERR".into(),
+        2 => "SERVFAIL".into(),
+        3 => "NXDOMAIN".into(),
+        4 => "NOTIMP".into(),
+        5 => "REFUSED".into(),
+        6 => "YXDOMAIN".into(),
+        7 => "YXRRSET".into(),
+        8 => "NXRRSET".into(),
+        9 => "NOTAUTH".into(),
+        10 => "NOTZONE".into(),
+        _ => rcode.to_string(),
+    }
+}
+
+/// DHCID of a client for a name, RFC 4701 section 3.3. Leases are kept by
+/// hardware address, so clients are identified by it rather than by their
+/// client identifier : the digest covers the Ethernet htype, the address
+/// and the name in the wire format.
+pub fn dhcid(mac: &str, fqdn: &str) -> Vec<u8> {
+    let mut identity = vec![HTYPE_ETHERNET];
+    identity.extend(
+        mac.split(':')
+            .filter_map(|byte| u8::from_str_radix(byte, 16).ok()),
+    );
+    identity.extend(encode_name(&fqdn.to_ascii_lowercase()));
+    let mut rdata = DHCID_HARDWARE_ADDRESS.to_be_bytes().to_vec();
+    rdata.push(DHCID_SHA256);
+    rdata.extend_from_slice(&sha2::Sha256::digest(&identity));
+    rdata
+}
+
+/// "4.3.2.1.in-addr.arpa." for 1.2.3.4
+pub fn reverse_name(address: Ipv4Addr) -> String {
+    let [a, b, c, d] = address.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa.", d, c, b, a)
+}
+
+/// Domain name in the DNS wire format, without compression
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        let label = &label.as_bytes()[..label.len().min(63)];
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label);
+    }
+    bytes.push(0);
+    bytes
+}
@@ -0,0 +1,303 @@
+//! Failover between two servers.
+//!
+//! Free addresses are split between the peers : the primary allocates the
+//! even addresses and the secondary the odd ones, so they never offer the
+//! same address. Every lease committed on one side is sent to the other one
+//! over a TCP connection, so both can renew any client.
+//!
+//! The primary listens on the failover address and the secondary connects
+//! to it. Both prove they know the shared secret before anything else is
+//! sent, then send a heartbeat every second, a peer not heard of during the
+//! heartbeat timeout is considered unreachable. Updates are queued while the
+//! peer is unreachable and sent once it is back.
+//!
+//!   NORMAL --(connection lost)--> COMMUNICATIONS-INTERRUPTED
+//!   COMMUNICATIONS-INTERRUPTED --(operator)--> PARTNER-DOWN
+//!   any --(connection established)--> NORMAL
+//!
+//! A lost connection doesn't tell a stopped peer from a network partition,
+//! so only an operator moves a server to PARTNER-DOWN, through the
+//! management API. The peer may still renew the leases it gave for up to
+//! the maximum client lead time (MCLT), so the addresses of its half are
+//! allocated once the MCLT has elapsed in PARTNER-DOWN, never before.
+
+use hmac::{Mac, SimpleHmac};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::api::constant_time_eq;
+use crate::configuration::Failover;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Primary,
+    Secondary,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Normal,
+    CommunicationsInterrupted,
+    PartnerDown,
+}
+
+/// Binding change sent to the peer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    /// mac, address, end of the lease as a unix timestamp, hostname
+    Bind(String, Ipv4Addr, u64, String),
+    /// mac, address
+    Release(String, Ipv4Addr),
+}
+
+/// What the failover threads tell the server
+#[derive(Debug)]
+pub enum Event {
+    Connected,
+    Disconnected,
+    Update(Update),
+}
+
+/// State machine, driven by the server loop
+pub struct FailoverState {
+    pub role: Role,
+    pub state: State,
+    since: Instant,
+    mclt: Duration,
+}
+
+impl FailoverState {
+    pub fn new(configuration: &Failover) -> Self {
+        FailoverState {
+            role: configuration.role,
+            state: State::CommunicationsInterrupted,
+            since: Instant::now(),
+            mclt: Duration::from_secs(configuration.mclt),
+        }
+    }
+    pub fn on_event(&mut self, event: &Event) {
+        match event {
+            Event::Connected => self.transition(State::Normal),
+            Event::Disconnected => self.transition(State::CommunicationsInterrupted),
+            Event::Update(_) => {}
+        }
+    }
+    /// The operator confirms the peer is down, refused while it is connected
+    pub fn partner_down(&mut self) -> Result<(), String> {
+        if self.state == State::Normal {
+            return Err("the failover peer is connected".to_string());
+        }
+        self.transition(State::PartnerDown);
+        Ok(())
+    }
+    fn transition(&mut self, state: State) {
+        if self.state != state {
+            info!(from = ?self.state, to = ?state, "failover state changed");
+            self.state = state;
+            self.since = Instant::now();
+        }
+    }
+    /// Whether this server may allocate a free address
+    pub fn owns(&self, addr: Ipv4Addr) -> bool {
+        if self.state == State::PartnerDown && self.since.elapsed() >= self.mclt {
+            return true;
+        }
+        let even = u32::from(addr) % 2 == 0;
+        match self.role {
+            Role::Primary => even,
+            Role::Secondary => !even,
+        }
+    }
+}
+
+impl Update {
+    fn to_line(&self) -> String {
+        match self {
+            Update::Bind(mac, address, expire, hostname) => {
+                format!("BIND {} {} {} {}\n", mac, address, expire, hostname)
+            }
+            Update::Release(mac, address) => format!("RELEASE {} {}\n", mac, address),
+        }
+    }
+    fn from_line(line: &str) -> std::option::Option<Update> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["BIND", mac, address, expire, hostname @ ..] => Some(Update::Bind(
+                mac.to_string(),
+                address.parse().ok()?,
+                expire.parse().ok()?,
+                hostname.first().unwrap_or(&"").to_string(),
+            )),
+            ["RELEASE", mac, address] => {
+                Some(Update::Release(mac.to_string(), address.parse().ok()?))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Start the connection to the peer, updates read from `outgoing` are sent to
+/// it and everything received comes back through `events`. The primary binds
+/// the failover address before returning.
+pub fn start(
+    configuration: Failover,
+    outgoing: Receiver<Update>,
+    events: Sender<Event>,
+) -> io::Result<()> {
+    let listener = match configuration.role {
+        Role::Primary => Some(TcpListener::bind(configuration.address)?),
+        Role::Secondary => None,
+    };
+    thread::spawn(move || {
+        // Update which could not be written when the connection was lost
+        let mut pending = None;
+        loop {
+            let stream = match &listener {
+                Some(listener) => listener.accept().map(|(stream, _)| stream),
+                None => TcpStream::connect(configuration.address),
+            };
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) =
+                        connection(stream, &configuration, &outgoing, &events, &mut pending)
+                    {
+                        warn!(error = %e, "failover connection lost");
+                    }
+                    if events.send(Event::Disconnected).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => thread::sleep(RECONNECT_INTERVAL),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn connection(
+    stream: TcpStream,
+    configuration: &Failover,
+    outgoing: &Receiver<Update>,
+    events: &Sender<Event>,
+    pending: &mut Option<Update>,
+) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(Duration::from_secs(configuration.heartbeat_timeout)))?;
+    stream.set_nodelay(true)?;
+    let mut writer = stream;
+    let mut reader = BufReader::new(writer.try_clone()?);
+    if let Err(e) = authenticate(&mut writer, &mut reader, configuration) {
+        warn!(%peer, error = %e, "failover peer not authenticated");
+        let _ = writer.shutdown(Shutdown::Both);
+        return Err(e);
+    }
+    info!(%peer, "failover peer connected");
+    // The updates queued while disconnected are sent first, the server then
+    // queues all its bindings
+    let _ = events.send(Event::Connected);
+
+    let reader_events = events.clone();
+    let reader = thread::spawn(move || {
+        for line in (&mut reader).lines() {
+            let line = match line {
+                Ok(line) => line,
+                // Timeout or closed connection, the writer notices on its next write
+                Err(_) => break,
+            };
+            if let Some(update) = Update::from_line(&line) {
+                if reader_events.send(Event::Update(update)).is_err() {
+                    break;
+                }
+            }
+        }
+        let _ = reader.get_ref().shutdown(Shutdown::Both);
+    });
+
+    let result = loop {
+        let update = match pending.take() {
+            Some(update) => Some(update),
+            None => match outgoing.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(update) => Some(update),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break Ok(()),
+            },
+        };
+        let line = update
+            .as_ref()
+            .map_or_else(|| "PING\n".to_string(), Update::to_line);
+        if let Err(e) = writer.write_all(line.as_bytes()) {
+            *pending = update;
+            break Err(e);
+        }
+    };
+    let _ = writer.shutdown(Shutdown::Both);
+    let _ = reader.join();
+    result
+}
+
+/// Each side sends a random challenge and answers the one of its peer with
+/// an HMAC of it, keyed by the shared secret. The role of the sender is part
+/// of the answer so that a challenge can't be answered by sending it back.
+fn authenticate(
+    writer: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    configuration: &Failover,
+) -> io::Result<()> {
+    let challenge = hex(&rand::thread_rng().gen::<[u8; 16]>());
+    writer.write_all(format!("HELLO {}\n", challenge).as_bytes())?;
+    let peer_challenge = read_field(reader, "HELLO")?;
+    let answer = proof(&configuration.secret, configuration.role, &peer_challenge);
+    writer.write_all(format!("AUTH {}\n", answer).as_bytes())?;
+    let peer_role = match configuration.role {
+        Role::Primary => Role::Secondary,
+        Role::Secondary => Role::Primary,
+    };
+    let expected = proof(&configuration.secret, peer_role, &challenge);
+    if constant_time_eq(read_field(reader, "AUTH")?.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the peer doesn't know the shared secret",
+        ))
+    }
+}
+
+/// Value of a "<keyword> <value>" line
+fn read_field(reader: &mut BufReader<TcpStream>, keyword: &str) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match line.trim_end().split_once(' ') {
+        Some((found, value)) if found == keyword => Ok(value.to_string()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {} from the peer", keyword),
+        )),
+    }
+}
+
+/// Answer of `role` to a challenge, hex encoded
+fn proof(secret: &str, role: Role, challenge: &str) -> String {
+    let mut mac = SimpleHmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    let role = match role {
+        Role::Primary => "primary",
+        Role::Secondary => "secondary",
+    };
+    mac.update(format!("{} {}", role, challenge).as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
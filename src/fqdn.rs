@@ -0,0 +1,261 @@
+//! Client FQDN option (81), RFC 4702.
+//!
+//! Flags :
+//! 'S'  the server should update the A record
+//! 'O'  the server overrode the client choice (replies only)
+//! 'E'  the name is in the DNS wire format instead of ASCII
+//! 'N'  the server should not do any update
+
+use dhcp_proto::options::*;
+use dhcp_proto::Message;
+
+const FLAG_S: u8 = 0x01;
+const FLAG_O: u8 = 0x02;
+const FLAG_E: u8 = 0x04;
+const FLAG_N: u8 = 0x08;
+
+// Deprecated RCODE1 and RCODE2 fields, set to 255 by servers
+const RCODE_SERVER: u8 = 255;
+
+const LABEL_MAX: usize = 63;
+const NAME_MAX: usize = 253;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientFqdn {
+    pub flags: u8,
+    pub name: String,
+}
+
+/// Records the server takes care of for a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Updates {
+    /// A record
+    pub forward: bool,
+    /// PTR record
+    pub reverse: bool,
+}
+
+impl ClientFqdn {
+    pub fn from_message(msg: &Message) -> std::option::Option<ClientFqdn> {
+        let op = msg.option(Option::ClientFQDN)?;
+        let flags = *op.data.first()?;
+        let name = op.data.get(3..).unwrap_or_default();
+        let name = if flags & FLAG_E != 0 {
+            decode_name(name)
+        } else {
+            String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_string()
+        };
+        Some(ClientFqdn { flags, name })
+    }
+    pub fn wire_format(&self) -> bool {
+        self.flags & FLAG_E != 0
+    }
+    /**
+     * Decide which records the server updates. Unless the configuration lets
+     * clients update their own A record, the server does both and overrides
+     * the client choice.
+     */
+    pub fn updates(&self, client_updates: bool) -> Updates {
+        if !client_updates {
+            return Updates {
+                forward: true,
+                reverse: true,
+            };
+        }
+        if self.flags & FLAG_N != 0 {
+            Updates::default()
+        } else {
+            Updates {
+                forward: self.flags & FLAG_S != 0,
+                reverse: true,
+            }
+        }
+    }
+    /// Option 81 for the DHCPACK, `fqdn` being the name the server settled on
+    pub fn reply(&self, fqdn: &str, updates: Updates) -> std::option::Option<OptionSubfield> {
+        let mut flags: u8 = self.flags & FLAG_E;
+        if updates.forward {
+            flags |= FLAG_S;
+            if self.flags & FLAG_S == 0 {
+                flags |= FLAG_O;
+            }
+        }
+        if !updates.forward && !updates.reverse {
+            flags |= FLAG_N;
+        }
+        let mut data: Vec<u8> = vec![flags, RCODE_SERVER, RCODE_SERVER];
+        if self.wire_format() {
+            data.extend(crate::ddns::encode_name(fqdn));
+        } else {
+            data.extend_from_slice(fqdn.as_bytes());
+        }
+        OptionSubfield::new(Option::ClientFQDN, data).ok()
+    }
+}
+
+/**
+ * Turn a name sent by a client into a valid host name (RFC 1123) : lower
+ * case letters, digits and hyphens, labels not starting nor ending with an
+ * hyphen and at most 63 characters long.
+ */
+pub fn sanitize(name: &str) -> String {
+    let mut labels: Vec<String> = Vec::new();
+    for label in name.trim_end_matches('\0').split('.') {
+        let label: String = label
+            .chars()
+            .map(|c| c.to_ascii_lowercase())
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let label: String = label.trim_matches('-').chars().take(LABEL_MAX).collect();
+        let label = label.trim_end_matches('-');
+        if !label.is_empty() {
+            labels.push(label.to_string());
+        }
+    }
+    let mut name = labels.join(".");
+    while name.len() > NAME_MAX {
+        match name.rfind('.') {
+            Some(index) => name.truncate(index),
+            None => name.truncate(NAME_MAX),
+        }
+    }
+    name
+}
+
+/// Domain name in the DNS wire format, without compression
+fn decode_name(mut input: &[u8]) -> String {
+    let mut labels: Vec<String> = Vec::new();
+    while let Some((&len, rest)) = input.split_first() {
+        let len = usize::from(len);
+        if len == 0 || len > rest.len() {
+            break;
+        }
+        labels.push(String::from_utf8_lossy(&rest[..len]).to_string());
+        input = &rest[len..];
+    }
+    labels.join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcp_proto::MAGIC_COOKIE;
+
+    const BOTH: Updates = Updates {
+        forward: true,
+        reverse: true,
+    };
+
+    /// DHCPREQUEST carrying option 81
+    fn request(flags: u8, name: &[u8]) -> Message {
+        let mut packet = vec![0u8; 236];
+        packet[..3].copy_from_slice(&[1, 1, 6]);
+        packet.extend_from_slice(&MAGIC_COOKIE);
+        packet.extend_from_slice(&[Option::DHCPMessageType as u8, 1, 3]);
+        packet.extend_from_slice(&[Option::ClientFQDN as u8, 3 + name.len() as u8, flags, 0, 0]);
+        packet.extend_from_slice(name);
+        packet.push(255);
+        Message::deserialize(&packet).unwrap()
+    }
+
+    fn fqdn(flags: u8) -> ClientFqdn {
+        ClientFqdn {
+            flags,
+            name: "host".to_string(),
+        }
+    }
+
+    #[test]
+    fn names() {
+        let ascii = ClientFqdn::from_message(&request(FLAG_S, b"host.example.com\0")).unwrap();
+        assert_eq!(ascii.name, "host.example.com");
+        assert!(!ascii.wire_format());
+        let wire = b"\x04host\x07example\x03com\x00";
+        let encoded = ClientFqdn::from_message(&request(FLAG_S | FLAG_E, wire)).unwrap();
+        assert_eq!(encoded.name, "host.example.com");
+        assert!(encoded.wire_format());
+        // A label longer than what is left is dropped
+        let truncated = ClientFqdn::from_message(&request(FLAG_E, b"\x04host\x09exa")).unwrap();
+        assert_eq!(truncated.name, "host");
+    }
+
+    #[test]
+    fn updates() {
+        let reverse_only = Updates {
+            forward: false,
+            reverse: true,
+        };
+        for (flags, client_updates, expected) in [
+            // The server does both unless clients may update their A record
+            (0, false, BOTH),
+            (FLAG_N, false, BOTH),
+            (FLAG_S, true, BOTH),
+            (0, true, reverse_only),
+            (FLAG_N, true, Updates::default()),
+        ] {
+            assert_eq!(
+                fqdn(flags).updates(client_updates),
+                expected,
+                "flags {:#x}, client updates {}",
+                flags,
+                client_updates
+            );
+        }
+    }
+
+    #[test]
+    fn reply_flags() {
+        let flags = |client: u8, client_updates: bool| {
+            let fqdn = fqdn(client);
+            let reply = fqdn
+                .reply("host.example.com", fqdn.updates(client_updates))
+                .unwrap();
+            assert_eq!(reply.data[1..3], [RCODE_SERVER, RCODE_SERVER]);
+            reply.data[0]
+        };
+        // The server updates the A record the client wanted to update itself
+        assert_eq!(flags(0, false), FLAG_S | FLAG_O);
+        assert_eq!(flags(FLAG_N, false), FLAG_S | FLAG_O);
+        assert_eq!(flags(FLAG_S, false), FLAG_S);
+        assert_eq!(flags(FLAG_S, true), FLAG_S);
+        assert_eq!(flags(0, true), 0);
+        assert_eq!(flags(FLAG_N, true), FLAG_N);
+    }
+
+    #[test]
+    fn reply_encoding() {
+        let ascii = fqdn(FLAG_S).reply("host.example.com", BOTH).unwrap();
+        assert_eq!(&ascii.data[3..], b"host.example.com");
+        let wire = fqdn(FLAG_S | FLAG_E)
+            .reply("host.example.com.", BOTH)
+            .unwrap();
+        assert_eq!(wire.data[0], FLAG_S | FLAG_E);
+        assert_eq!(&wire.data[3..], b"\x04host\x07example\x03com\x00");
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("My_Laptop.Example.COM"), "my-laptop.example.com");
+        assert_eq!(sanitize("-host name-"), "host-name");
+        assert_eq!(sanitize("host..example.\0"), "host.example");
+        // Nothing valid is left
+        assert_eq!(sanitize("--.__\0"), "");
+        assert_eq!(sanitize(""), "");
+    }
+
+    #[test]
+    fn length_limits() {
+        let label = "a".repeat(70);
+        assert_eq!(sanitize(&label), "a".repeat(LABEL_MAX));
+        // No hyphen is left at the end of the truncated label
+        let hyphen = format!("{}-b", "a".repeat(LABEL_MAX - 1));
+        assert_eq!(sanitize(&hyphen), "a".repeat(LABEL_MAX - 1));
+        // Whole labels are dropped from the end
+        let long = vec!["b".repeat(60); 5].join(".");
+        let name = sanitize(&long);
+        assert_eq!(name, vec!["b".repeat(60); 4].join("."));
+        assert!(name.len() <= NAME_MAX);
+    }
+}
@@ -0,0 +1,17 @@
+mod api;
+pub mod balancing;
+mod capture;
+mod check;
+pub mod configuration;
+pub mod ddns;
+pub mod failover;
+pub mod logging;
+pub mod fqdn;
+pub mod server;
+mod metrics;
+pub mod cli;
+pub mod ctl;
+pub mod boot;
+pub mod probe;
+pub mod tftp;
+pub mod transport;
@@ -0,0 +1,31 @@
+use std::io::IsTerminal;
+use tracing::Level;
+
+use crate::cli::{LogFormat, LogLevel};
+
+/**
+ * Logs are written to the standard output, as text or as one JSON object
+ * per line. Events about a DHCP message carry the fields of the message
+ * span : xid, chaddr and message type.
+ */
+pub fn init(level: LogLevel, format: LogFormat) {
+    let level = match level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+        LogLevel::Trace => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(std::io::stdout().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+}
@@ -1,17 +1,41 @@
-#![allow(warnings)] // warning annoying
-
-mod configuration;
-mod server;
-mod message;
-mod cli;
+use rdhcp::{cli, configuration, ctl, logging, server};
 
 fn main() {
     //println!("Default configuration example \n{}", configuration::Configuration::default().to_toml());
     // May be usefull when I will look at the broadcast flag
     //socket.set_broadcast(true)?;
     
-    cli::handle();
-    let server = server::DhcpServer::new();
-    println!("INFO: server started on 127.0.0.1:67\n");
-    server.on_recv();
+    let args = cli::handle();
+    let command = match (&args.command, args.check) {
+        (None, true) => Some(&cli::Command::Validate),
+        (command, _) => command.as_ref(),
+    };
+    if let Some(command) = command {
+        if let Err(e) = ctl::run(&args, command) {
+            eprintln!("ERR: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let path = match args.configuration_path("to run the server") {
+        Ok(path) => path.to_string(),
+        Err(e) => {
+            eprintln!("ERR: {}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(args.log_level, args.log_format);
+    tracing::info!(%path, "loading the configuration file");
+    let configuration = configuration::Configuration::read(path)
+        .expect("ERR: unable to load the configuration");
+    let problems = configuration.validate();
+    if !problems.is_empty() {
+        for problem in problems.iter() {
+            tracing::error!(%problem, "invalid configuration");
+        }
+        std::process::exit(1);
+    }
+    let server = server::DhcpServer::new(configuration.listen);
+    tracing::info!(address = %configuration.listen, "server started");
+    server.on_recv(configuration);
 }
@@ -0,0 +1,366 @@
+//! Prometheus metrics, in the text exposition format on GET /metrics.
+//!
+//! Counters and histograms are updated by the server loop, the pool gauges
+//! are asked to the server loop at scrape time through the same channel as
+//! the management API.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+use tracing::error;
+
+use crate::api::{Command, RangeUsage, Request, SubnetUsage};
+use crate::configuration::Metrics;
+use dhcp_proto::MessageType;
+
+/// Upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 2.5];
+/// Reasons of the DHCPNAK sent by the server
+const NAK_REASONS: [&str; 3] = ["wrong_address", "outside_range", "unknown_subnet"];
+/// Messages sent by a server
+const SENT_TYPES: [MessageType; 3] = [
+    MessageType::DHCPOFFER,
+    MessageType::DHCPACK,
+    MessageType::DHCPNAK,
+];
+
+#[derive(Default)]
+pub struct Registry {
+    inner: Mutex<Counters>,
+}
+
+struct Counters {
+    received: BTreeMap<String, u64>,
+    sent: BTreeMap<String, u64>,
+    naks: BTreeMap<&'static str, u64>,
+    parse_failures: u64,
+    allocation: Histogram,
+    lease_store: Histogram,
+}
+
+/// The known label values start at zero, so that rate() sees their first
+/// increment
+impl Default for Counters {
+    fn default() -> Self {
+        let received = (1..=8)
+            .filter_map(|value| MessageType::try_from(value).ok())
+            .map(|message_type| (format!("{:?}", message_type), 0))
+            .collect();
+        let sent = SENT_TYPES
+            .iter()
+            .map(|message_type| (format!("{:?}", message_type), 0))
+            .collect();
+        Counters {
+            received,
+            sent,
+            naks: NAK_REASONS.iter().map(|reason| (*reason, 0)).collect(),
+            parse_failures: 0,
+            allocation: Histogram::default(),
+            lease_store: Histogram::default(),
+        }
+    }
+}
+
+struct Histogram {
+    /// Observations lower or equal to each bound of BUCKETS
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if seconds <= bound {
+                *count += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+    fn render(&self, output: &mut String, name: &str, help: &str) {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(output, "{}_sum {}", name, self.sum);
+        let _ = writeln!(output, "{}_count {}", name, self.count);
+    }
+}
+
+impl Registry {
+    pub fn received(&self, message_type: &MessageType) {
+        let mut counters = self.inner.lock().unwrap();
+        *counters
+            .received
+            .entry(format!("{:?}", message_type))
+            .or_default() += 1;
+    }
+    pub fn sent(&self, message_type: &MessageType) {
+        let mut counters = self.inner.lock().unwrap();
+        *counters
+            .sent
+            .entry(format!("{:?}", message_type))
+            .or_default() += 1;
+    }
+    pub fn nak(&self, reason: &'static str) {
+        *self.inner.lock().unwrap().naks.entry(reason).or_default() += 1;
+    }
+    pub fn parse_failure(&self) {
+        self.inner.lock().unwrap().parse_failures += 1;
+    }
+    /// Time taken to pick an address, probes included
+    pub fn allocation(&self, duration: Duration) {
+        self.inner.lock().unwrap().allocation.observe(duration);
+    }
+    /// Time taken to write the leases file
+    pub fn lease_store(&self, duration: Duration) {
+        self.inner.lock().unwrap().lease_store.observe(duration);
+    }
+    pub fn render(&self, pools: &[SubnetUsage]) -> String {
+        let counters = self.inner.lock().unwrap();
+        let mut output = String::new();
+        let labelled =
+            |output: &mut String, name: &str, help: &str, label: &str, values: Vec<(&str, u64)>| {
+                let _ = writeln!(output, "# HELP {} {}", name, help);
+                let _ = writeln!(output, "# TYPE {} counter", name);
+                for (value, count) in values {
+                    let _ = writeln!(output, "{}{{{}=\"{}\"}} {}", name, label, value, count);
+                }
+            };
+        labelled(
+            &mut output,
+            "rdhcp_messages_received_total",
+            "DHCP messages received by type.",
+            "type",
+            counters
+                .received
+                .iter()
+                .map(|(k, v)| (k.as_str(), *v))
+                .collect(),
+        );
+        labelled(
+            &mut output,
+            "rdhcp_messages_sent_total",
+            "DHCP messages sent by type.",
+            "type",
+            counters
+                .sent
+                .iter()
+                .map(|(k, v)| (k.as_str(), *v))
+                .collect(),
+        );
+        labelled(
+            &mut output,
+            "rdhcp_naks_total",
+            "DHCPNAK sent by reason.",
+            "reason",
+            counters.naks.iter().map(|(k, v)| (*k, *v)).collect(),
+        );
+        let _ = writeln!(
+            output,
+            "# HELP rdhcp_parse_failures_total Packets which are not valid DHCP messages."
+        );
+        let _ = writeln!(output, "# TYPE rdhcp_parse_failures_total counter");
+        let _ = writeln!(
+            output,
+            "rdhcp_parse_failures_total {}",
+            counters.parse_failures
+        );
+
+        range_gauge(
+            &mut output,
+            "rdhcp_range_size",
+            "Addresses in the range.",
+            pools,
+            |range| range.size,
+        );
+        range_gauge(
+            &mut output,
+            "rdhcp_range_used",
+            "Offered, bound or abandoned addresses of the range.",
+            pools,
+            |range| range.used,
+        );
+
+        counters.allocation.render(
+            &mut output,
+            "rdhcp_allocation_duration_seconds",
+            "Time taken to allocate an address.",
+        );
+        counters.lease_store.render(
+            &mut output,
+            "rdhcp_lease_store_write_duration_seconds",
+            "Time taken to write the leases file.",
+        );
+        output
+    }
+}
+
+fn range_gauge(
+    output: &mut String,
+    name: &str,
+    help: &str,
+    pools: &[SubnetUsage],
+    value: fn(&RangeUsage) -> usize,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} gauge", name);
+    for subnet in pools.iter() {
+        for range in subnet.ranges.iter() {
+            let _ = writeln!(
+                output,
+                "{}{{subnet=\"{}\",range=\"{}-{}\"}} {}",
+                name,
+                subnet.network,
+                range.start_address,
+                range.end_address,
+                value(range)
+            );
+        }
+    }
+}
+
+pub struct MetricsServer {
+    server: Server,
+}
+
+impl MetricsServer {
+    pub fn new(configuration: &Metrics) -> Result<MetricsServer, String> {
+        Ok(MetricsServer {
+            server: Server::http(configuration.address).map_err(|e| e.to_string())?,
+        })
+    }
+    pub fn on_recv(&self, registry: &Registry, commands: Sender<Command>) {
+        for request in self.server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let pools = pools(&commands).unwrap_or_default();
+                let content_type =
+                    Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+                        .expect("static header is valid");
+                Response::from_string(registry.render(&pools)).with_header(content_type)
+            } else {
+                Response::from_string("not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                error!(error = %e, "metrics unable to answer");
+            }
+        }
+    }
+}
+
+/// Utilisation of the pools, from the server loop
+fn pools(commands: &Sender<Command>) -> Option<Vec<SubnetUsage>> {
+    let (reply, answer) = mpsc::channel();
+    commands
+        .send(Command {
+            request: Request::Pools,
+            reply,
+        })
+        .ok()?;
+    let document = answer.recv().ok()?.ok()?;
+    serde_json::from_value(document).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn samples(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect()
+    }
+
+    #[test]
+    fn known_series_start_at_zero() {
+        let output = Registry::default().render(&[]);
+        let samples = samples(&output);
+        for sample in [
+            "rdhcp_messages_received_total{type=\"DHCPDISCOVER\"} 0",
+            "rdhcp_messages_received_total{type=\"DHCPINFORM\"} 0",
+            "rdhcp_messages_sent_total{type=\"DHCPOFFER\"} 0",
+            "rdhcp_messages_sent_total{type=\"DHCPNAK\"} 0",
+            "rdhcp_naks_total{reason=\"wrong_address\"} 0",
+            "rdhcp_naks_total{reason=\"outside_range\"} 0",
+            "rdhcp_naks_total{reason=\"unknown_subnet\"} 0",
+            "rdhcp_parse_failures_total 0",
+            "rdhcp_allocation_duration_seconds_count 0",
+        ] {
+            assert!(samples.contains(&sample), "{} missing", sample);
+        }
+        // Servers don't receive their own messages
+        assert!(!output.contains("rdhcp_messages_sent_total{type=\"DHCPDISCOVER\"}"));
+    }
+
+    #[test]
+    fn exposition() {
+        let registry = Registry::default();
+        registry.received(&MessageType::DHCPDISCOVER);
+        registry.received(&MessageType::DHCPDISCOVER);
+        registry.sent(&MessageType::DHCPOFFER);
+        registry.nak("outside_range");
+        registry.parse_failure();
+        registry.allocation(Duration::from_millis(3));
+        let pools = [SubnetUsage {
+            network: Ipv4Addr::new(192, 0, 2, 0),
+            subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            size: 10,
+            used: 4,
+            ranges: vec![RangeUsage {
+                start_address: Ipv4Addr::new(192, 0, 2, 10),
+                end_address: Ipv4Addr::new(192, 0, 2, 19),
+                size: 10,
+                used: 4,
+            }],
+        }];
+        let output = registry.render(&pools);
+        let samples = samples(&output);
+        for sample in [
+            "rdhcp_messages_received_total{type=\"DHCPDISCOVER\"} 2",
+            "rdhcp_messages_sent_total{type=\"DHCPOFFER\"} 1",
+            "rdhcp_naks_total{reason=\"outside_range\"} 1",
+            "rdhcp_naks_total{reason=\"wrong_address\"} 0",
+            "rdhcp_parse_failures_total 1",
+            "rdhcp_range_size{subnet=\"192.0.2.0\",range=\"192.0.2.10-192.0.2.19\"} 10",
+            "rdhcp_range_used{subnet=\"192.0.2.0\",range=\"192.0.2.10-192.0.2.19\"} 4",
+            // Buckets are cumulative
+            "rdhcp_allocation_duration_seconds_bucket{le=\"0.001\"} 0",
+            "rdhcp_allocation_duration_seconds_bucket{le=\"0.005\"} 1",
+            "rdhcp_allocation_duration_seconds_bucket{le=\"2.5\"} 1",
+            "rdhcp_allocation_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "rdhcp_allocation_duration_seconds_sum 0.003",
+            "rdhcp_allocation_duration_seconds_count 1",
+        ] {
+            assert!(samples.contains(&sample), "{} missing", sample);
+        }
+        // Every family has its HELP and TYPE lines
+        for (name, kind) in [
+            ("rdhcp_messages_received_total", "counter"),
+            ("rdhcp_naks_total", "counter"),
+            ("rdhcp_range_used", "gauge"),
+            ("rdhcp_lease_store_write_duration_seconds", "histogram"),
+        ] {
+            assert!(output.contains(&format!("# HELP {} ", name)));
+            assert!(output.contains(&format!("# TYPE {} {}\n", name, kind)));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// Check if an address is already used by someone on the network before
+/// offering it. Implemented as a trait so the pool can be driven without
+/// raw sockets.
+pub trait Prober {
+    /// Return true if something answered on `addr` before `timeout`
+    fn is_in_use(&self, addr: Ipv4Addr, timeout: Duration) -> bool;
+}
+
+/// Never sees anyone, used when conflict detection is disabled
+pub struct NoProber;
+impl Prober for NoProber {
+    fn is_in_use(&self, _addr: Ipv4Addr, _timeout: Duration) -> bool {
+        false
+    }
+}
+
+/// Send an ICMP echo request and wait for the reply.
+///
+/// An unprivileged ICMP datagram socket is tried first (linux ping_group_range),
+/// then a raw socket which require CAP_NET_RAW.
+pub struct IcmpProber {
+    identifier: u16,
+    /// Sequence of the last echo request, so late replies to a previous
+    /// probe are not taken for an answer
+    sequence: AtomicU16,
+}
+impl Default for IcmpProber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl IcmpProber {
+    pub fn new() -> Self {
+        IcmpProber {
+            identifier: std::process::id() as u16,
+            sequence: AtomicU16::new(0),
+        }
+    }
+    fn open_socket() -> std::io::Result<Socket> {
+        Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))
+            .or_else(|_| Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)))
+    }
+    fn echo_request(&self, sequence: u16) -> Vec<u8> {
+        let mut packet: Vec<u8> = vec![ICMP_ECHO_REQUEST, 0, 0, 0];
+        packet.extend_from_slice(&self.identifier.to_be_bytes());
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(b"rdhcp");
+        let checksum = checksum(&packet);
+        packet[2..=3].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+    fn ping(&self, addr: Ipv4Addr, timeout: Duration) -> std::io::Result<bool> {
+        let mut socket = IcmpProber::open_socket()?;
+        socket.connect(&SocketAddrV4::new(addr, 0).into())?;
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        socket.send(&self.echo_request(sequence))?;
+        // Datagram sockets get their identifier from the kernel, it is the
+        // port they are bound to
+        let identifier = match socket.r#type()? {
+            Type::DGRAM => socket
+                .local_addr()?
+                .as_socket_ipv4()
+                .map_or(self.identifier, |local| local.port()),
+            _ => self.identifier,
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0u8; 1500];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            socket.set_read_timeout(Some(deadline - now))?;
+            let num_byte = match socket.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    return Ok(false)
+                }
+                Err(e) => return Err(e),
+            };
+            if is_echo_reply(&buffer[..num_byte], identifier, sequence) {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Whether a packet read from an ICMP socket answers the echo request
+/// `identifier` and `sequence`. Raw sockets give the IP header too, it is
+/// skipped.
+pub fn is_echo_reply(packet: &[u8], identifier: u16, sequence: u16) -> bool {
+    let mut icmp = packet;
+    if !icmp.is_empty() && icmp[0] >> 4 == 4 {
+        let header_len = usize::from(icmp[0] & 0x0f) * 4;
+        icmp = &icmp[header_len.min(icmp.len())..];
+    }
+    match icmp {
+        [kind, _code, _, _, id_high, id_low, seq_high, seq_low, ..] => {
+            *kind == ICMP_ECHO_REPLY
+                && u16::from_be_bytes([*id_high, *id_low]) == identifier
+                && u16::from_be_bytes([*seq_high, *seq_low]) == sequence
+        }
+        _ => false,
+    }
+}
+
+impl Prober for IcmpProber {
+    fn is_in_use(&self, addr: Ipv4Addr, timeout: Duration) -> bool {
+        match self.ping(addr, timeout) {
+            Ok(answered) => answered,
+            Err(e) => {
+                warn!(%addr, error = %e, "unable to probe");
+                false
+            }
+        }
+    }
+}
+
+/// Internet checksum, RFC 1071
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => 0,
+        };
+        sum += u32::from(word);
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
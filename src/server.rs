@@ -1,26 +1,61 @@
 use rand::Rng;
-use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, UdpSocket};
-use std::time::Duration; //use pretty_hex::pretty_hex;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use serde_derive::Serialize;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH}; //use pretty_hex::pretty_hex;
+use tracing::{debug, error, info, info_span, trace, warn};
 
+use crate::api::{
+    self, ApiServer, Command, HistoryEntry, Lease, RangeUsage, Reply, Reservation, SubnetUsage,
+};
+use crate::boot;
+use crate::capture::PacketCapture;
 use crate::configuration::*;
-use crate::message::{options::*, *};
+use crate::ddns::DnsUpdater;
+use crate::failover::{self, Event, FailoverState, State, Update};
+use crate::fqdn::{self, ClientFqdn, Updates};
+use dhcp_proto::{options::*, *};
+use crate::metrics::{MetricsServer, Registry};
+use crate::probe::*;
+use crate::tftp::TftpServer;
+use crate::transport::Transport;
 
-// Default lease set to 2h, maybe change that in configuration later ?
-const DEFAULT_LEASE: Duration = Duration::new(7200, 0);
+// How often the leases are checked for expiration
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+// Events kept for each client
+const HISTORY_LENGTH: usize = 32;
+// Clients with a history, the one not seen for the longest time is forgotten
+pub const HISTORY_CLIENTS: usize = 4096;
+// Largest UDP payload over IPv4, clients may send more than 576 bytes
+const MAX_UDP_PAYLOAD: usize = 65507;
+// IP datagram every client accepts, RFC 2131 section 2
+const MINIMUM_DATAGRAM: usize = 576;
+// IPv4 and UDP headers, counted in the datagram size
+const IP_UDP_HEADERS: usize = 28;
 
 #[derive(PartialEq, Eq, Clone)]
 pub struct Client {
     address: Ipv4Addr,
     hostname: String,
     lease: Duration,
+    /// End of the lease, not set until the client is acknowledged
+    expire: std::option::Option<SystemTime>,
+    /// DNS records registered by the server for this client
+    updates: Updates,
 }
 impl Default for Client {
     fn default() -> Self {
         Client {
             address: Ipv4Addr::new(0, 0, 0, 0),
             hostname: String::new(),
-            lease: DEFAULT_LEASE,
+            lease: LeaseTime::default().negotiate(None),
+            expire: None,
+            updates: Updates::default(),
         }
     }
 }
@@ -36,52 +71,195 @@ impl Client {
             address,
             hostname,
             lease,
+            expire: None,
+            updates: Updates::default(),
         }
     }
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+}
+
+/// Lease as written in the leases file, expire is a unix timestamp
+#[derive(Serialize)]
+struct LeaseRecord {
+    hardware_address: String,
+    address: Ipv4Addr,
+    hostname: String,
+    expire: u64,
+}
+
+#[derive(Serialize)]
+struct LeaseDump {
+    lease: Vec<LeaseRecord>,
 }
 
 #[derive(Debug)]
 pub enum ErrorPool {
     AddressAlreadyAllocated,
     AddressOutOfRange,
+    PoolExhausted,
+    /// The probing time allowed for a single request is over
+    ProbeBudgetExhausted,
 }
 
 pub struct Pool {
     pub configuration: Configuration,
     pub reservation: HashMap<String, Client>,
+    /// Addresses which answered a probe while being free in the pool, or
+    /// were declined, and when
+    pub abandoned: HashMap<Ipv4Addr, Instant>,
+    /// Last events of each client, oldest first
+    pub history: HashMap<String, VecDeque<HistoryEntry>>,
+    /// Set when the pools are shared with a failover peer
+    pub failover: std::option::Option<FailoverState>,
+    pub metrics: Arc<Registry>,
+    prober: Box<dyn Prober>,
 }
 impl Pool {
     pub fn new(configuration: Configuration) -> Self {
+        let prober = prober_for(&configuration.conflict_detection);
+        Pool::with_prober(configuration, prober)
+    }
+    pub fn with_prober(configuration: Configuration, prober: Box<dyn Prober>) -> Self {
+        let failover = configuration.failover.as_ref().map(FailoverState::new);
         Pool {
             configuration,
             reservation: HashMap::new(),
+            abandoned: HashMap::new(),
+            history: HashMap::new(),
+            failover,
+            metrics: Arc::new(Registry::default()),
+            prober,
         }
     }
-    fn reserve_ip(&mut self, mac: String) -> Result<&Client, ErrorPool> {
-        let mut addr = (&self).random_addr();
-        let mut is_free = false;
-        while !is_free {
-            addr = self.random_addr();
-            is_free = match self.is_free(addr) {
-                Ok(free) => {
-                    // TODO : Try pinging before allocating
-                    free
+    /// Address offered to a client : the one it already holds, its fixed
+    /// address, or a free one of the ranges it is allowed in. The probes
+    /// of the free addresses stop once the probing budget is spent.
+    pub fn reserve_ip(
+        &mut self,
+        mac: String,
+        subnet: usize,
+        classes: &[String],
+    ) -> Result<&Client, ErrorPool> {
+        if self.reservation.contains_key(&mac) {
+            return Ok(self.reservation.get(&mac).unwrap());
+        }
+        self.reclaim_abandoned();
+        let subnet = &self.configuration.subnets[subnet];
+        if let Some(addr) = subnet.host(&mac).and_then(|host| host.fixed_address) {
+            if !self.is_free(addr)? {
+                return Err(ErrorPool::AddressAlreadyAllocated);
+            }
+            self.reservation.insert(mac.clone(), Client::init(addr));
+            return Ok(self.reservation.get(&mac).unwrap());
+        }
+
+        // Ranges preferred by the client classes are tried first
+        let allowed: Vec<&AddressRange> = subnet
+            .ranges
+            .iter()
+            .filter(|range| range.allows(classes))
+            .collect();
+        let (preferred, others): (Vec<&AddressRange>, Vec<&AddressRange>) =
+            allowed.into_iter().partition(|range| range.prefers(classes));
+        let timeout = Duration::from_millis(self.configuration.conflict_detection.timeout);
+        let budget = Duration::from_millis(self.configuration.conflict_detection.budget);
+        let started = Instant::now();
+        for ranges in [preferred, others] {
+            while self.used(&ranges) < self.capacity(&ranges) {
+                let addr = Pool::random_addr(&ranges);
+                if !self.owns(addr) || !self.is_free(addr)? {
+                    continue;
                 }
-                Err(e) => return Err(e),
-            };
+                let remaining = budget.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    return Err(ErrorPool::ProbeBudgetExhausted);
+                }
+                if self.prober.is_in_use(addr, timeout.min(remaining)) {
+                    warn!(%addr, "address answered to a probe, marking it as abandoned");
+                    self.abandoned.insert(addr, Instant::now());
+                    continue;
+                }
+                self.reservation.insert(mac.clone(), Client::init(addr));
+                return Ok(self.reservation.get(&mac).unwrap());
+            }
         }
-        self.reservation.insert(mac.clone(), Client::init(addr));
-        Ok(self.reservation.get(&mac).unwrap())
+        Err(ErrorPool::PoolExhausted)
     }
-    fn random_addr(&self) -> Ipv4Addr {
-        let rnd_addr: u32 = rand::thread_rng().gen_range(
-            self.configuration.range.start_address.into()
-                ..self.configuration.range.end_address.into(),
-        );
-        Ipv4Addr::from(rnd_addr)
+    fn random_addr(ranges: &[&AddressRange]) -> Ipv4Addr {
+        let mut index: u32 = rand::thread_rng().gen_range(0..Pool::size(ranges) as u32);
+        for range in ranges.iter() {
+            let start: u32 = range.start_address.into();
+            let end: u32 = range.end_address.into();
+            let len = end.saturating_sub(start) + 1;
+            if index < len {
+                return Ipv4Addr::from(start + index);
+            }
+            index -= len;
+        }
+        unreachable!("index is always lower than the ranges size")
+    }
+    fn size(ranges: &[&AddressRange]) -> usize {
+        ranges
+            .iter()
+            .map(|range| {
+                let start: u32 = range.start_address.into();
+                let end: u32 = range.end_address.into();
+                if end < start {
+                    0
+                } else {
+                    (end - start) as usize + 1
+                }
+            })
+            .sum()
+    }
+    /// Number of addresses of the ranges this server may allocate
+    fn capacity(&self, ranges: &[&AddressRange]) -> usize {
+        match &self.failover {
+            Some(failover) if failover.state != State::PartnerDown => ranges
+                .iter()
+                .flat_map(|range| u32::from(range.start_address)..=u32::from(range.end_address))
+                .filter(|addr| failover.owns(Ipv4Addr::from(*addr)))
+                .count(),
+            _ => Pool::size(ranges),
+        }
+    }
+    /// Number of addresses of the ranges, owned by this server, which are
+    /// reserved or abandoned
+    fn used(&self, ranges: &[&AddressRange]) -> usize {
+        let counted = |addr: &Ipv4Addr| {
+            ranges.iter().any(|range| range.contains(*addr)) && self.owns(*addr)
+        };
+        self.reservation
+            .values()
+            .filter(|client| counted(&client.address))
+            .count()
+            + self.abandoned.keys().filter(|addr| counted(addr)).count()
+    }
+    /// Put back in the pool the addresses abandoned for long enough
+    fn reclaim_abandoned(&mut self) {
+        let hold = Duration::from_secs(self.configuration.conflict_detection.abandon_time);
+        self.abandoned.retain(|addr, since| {
+            let held = since.elapsed() < hold;
+            if !held {
+                info!(%addr, "abandoned address back in the pool");
+            }
+            held
+        });
+    }
+    /// Whether a free address may be allocated by this server rather than
+    /// by its failover peer
+    fn owns(&self, addr: Ipv4Addr) -> bool {
+        self.failover
+            .as_ref()
+            .is_none_or(|failover| failover.owns(addr))
     }
     fn is_free(&self, addr: Ipv4Addr) -> Result<bool, ErrorPool> {
-        for (mac, client) in self.reservation.iter() {
+        if self.abandoned.contains_key(&addr) {
+            return Ok(false);
+        }
+        for client in self.reservation.values() {
             if client.address == addr {
                 return Ok(false);
             }
@@ -89,133 +267,1114 @@ impl Pool {
 
         Ok(true)
     }
+    /// Remove the leases which ended
+    fn expire(&mut self) -> Vec<(String, Client)> {
+        let now = SystemTime::now();
+        let expired: Vec<String> = self
+            .reservation
+            .iter()
+            .filter(|(_, client)| client.expire.is_some_and(|expire| expire <= now))
+            .map(|(mac, _)| mac.clone())
+            .collect();
+        let mut clients = Vec::new();
+        for mac in expired.iter() {
+            if let Some(client) = self.reservation.remove(mac) {
+                self.record(mac, "expired", client.address);
+                clients.push((mac.clone(), client));
+            }
+        }
+        clients
+    }
+    /// Add an event to the history of a client
+    pub fn record(&mut self, mac: &str, event: &'static str, address: Ipv4Addr) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        if self.history.len() >= HISTORY_CLIENTS && !self.history.contains_key(mac) {
+            let oldest = self
+                .history
+                .iter()
+                .min_by_key(|(_, history)| history.back().map_or(0, |entry| entry.time))
+                .map(|(mac, _)| mac.clone());
+            if let Some(oldest) = oldest {
+                self.history.remove(&oldest);
+            }
+        }
+        let history = self.history.entry(mac.to_string()).or_default();
+        if history.len() == HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(HistoryEntry {
+            time,
+            event,
+            address,
+        });
+    }
+    /**
+     * Hostname for a client : sanitized, with the subnet domain appended if
+     * configured so, and made unique by suffixing the end of the hardware
+     * address when another client already holds it.
+     */
+    fn hostname_for(&self, msg: &Message, subnet: usize) -> String {
+        let mut hostname = match client_hostname(msg) {
+            Some(hostname) => hostname,
+            None => return String::new(),
+        };
+        let subnet = &self.configuration.subnets[subnet];
+        if let (true, Some(domain)) = (subnet.append_domain, subnet.domain_name()) {
+            let domain = fqdn::sanitize(domain);
+            if !hostname.contains('.') && !domain.is_empty() {
+                hostname = format!("{}.{}", hostname, domain);
+            }
+        }
+        let mac = msg.mac_address();
+        let duplicate = self
+            .reservation
+            .iter()
+            .any(|(other, client)| *other != mac && client.hostname == hostname);
+        if duplicate {
+            let suffix: String = mac.replace(':', "");
+            let suffix = &suffix[suffix.len().saturating_sub(6)..];
+            let unique = match hostname.split_once('.') {
+                Some((label, domain)) => format!("{}-{}.{}", label, suffix, domain),
+                None => format!("{}-{}", hostname, suffix),
+            };
+            warn!(%hostname, %unique, "hostname already used, making it unique");
+            hostname = unique;
+        }
+        hostname
+    }
+    /// Write the acknowledged leases to the leases file, if there is one
+    fn dump_leases(&self) {
+        let path = match &self.configuration.leases_file {
+            Some(path) => path,
+            None => return,
+        };
+        let mut leases: Vec<LeaseRecord> = self
+            .reservation
+            .iter()
+            .filter_map(|(mac, client)| {
+                let expire = client.expire?.duration_since(UNIX_EPOCH).ok()?;
+                Some(LeaseRecord {
+                    hardware_address: mac.clone(),
+                    address: client.address,
+                    hostname: client.hostname.clone(),
+                    expire: expire.as_secs(),
+                })
+            })
+            .collect();
+        leases.sort_by_key(|lease| lease.address);
+        let dump = LeaseDump { lease: leases };
+        let content = toml::to_string(&dump).expect("leases should always be serializable");
+        // Write then rename so readers never see a partial file
+        let started = Instant::now();
+        let tmp_path = format!("{}.tmp", path);
+        if let Err(e) = fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, path)) {
+            error!(%path, error = %e, "unable to write the leases");
+        }
+        self.metrics.lease_store(started.elapsed());
+    }
+    /// Binding of an acknowledged client, as sent to the failover peer
+    fn binding(&self, mac: &str) -> std::option::Option<Update> {
+        let client = self.reservation.get(mac)?;
+        let expire = client.expire?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Update::Bind(
+            mac.to_string(),
+            client.address,
+            expire.as_secs(),
+            client.hostname.clone(),
+        ))
+    }
+    fn on_failover_event(&mut self, event: Event, peer: &Sender<Update>) {
+        if let Some(failover) = &mut self.failover {
+            failover.on_event(&event);
+        }
+        match event {
+            Event::Connected => {
+                // Send every binding, the peer may have missed some
+                let macs: Vec<String> = self.reservation.keys().cloned().collect();
+                for update in macs.iter().filter_map(|mac| self.binding(mac)) {
+                    let _ = peer.send(update);
+                }
+            }
+            Event::Disconnected => {}
+            Event::Update(update) => {
+                self.apply_update(update);
+                self.dump_leases();
+            }
+        }
+    }
+    /// Apply a binding change made by the failover peer. A binding of the
+    /// peer conflicting with a local one, for the same address or the same
+    /// client, replaces it only if it ends later.
+    pub fn apply_update(&mut self, update: Update) {
+        match update {
+            Update::Bind(mac, address, expire, hostname) => {
+                let expire = UNIX_EPOCH + Duration::from_secs(expire);
+                let conflicts: Vec<(String, Ipv4Addr, SystemTime)> = self
+                    .reservation
+                    .iter()
+                    .filter(|(other, client)| (**other == mac) != (client.address == address))
+                    .filter_map(|(other, client)| {
+                        Some((other.clone(), client.address, client.expire?))
+                    })
+                    .collect();
+                let longer = conflicts.iter().find(|(_, _, end)| *end >= expire);
+                if let Some((other, local, _)) = longer {
+                    warn!(
+                        chaddr = %mac,
+                        %address,
+                        local_chaddr = %other,
+                        local_address = %local,
+                        "binding of the failover peer conflicts with a longer local one, ignored"
+                    );
+                    return;
+                }
+                for (other, local, _) in conflicts.iter() {
+                    warn!(
+                        chaddr = %mac,
+                        %address,
+                        local_chaddr = %other,
+                        local_address = %local,
+                        "local binding replaced by a longer one of the failover peer"
+                    );
+                }
+                // The peer is the one which registered the DNS records, unless
+                // it renews a lease given by this server
+                let updates = match self.reservation.get(&mac) {
+                    Some(client) if client.address == address => client.updates,
+                    _ => Updates::default(),
+                };
+                // An offer of the address to another client is superseded
+                self.reservation
+                    .retain(|other, client| *other == mac || client.address != address);
+                self.reservation.insert(
+                    mac,
+                    Client {
+                        address,
+                        hostname,
+                        lease: expire.duration_since(SystemTime::now()).unwrap_or_default(),
+                        expire: Some(expire),
+                        updates,
+                    },
+                );
+            }
+            Update::Release(mac, address) => {
+                if self
+                    .reservation
+                    .get(&mac)
+                    .is_some_and(|client| client.address == address)
+                {
+                    self.reservation.remove(&mac);
+                }
+            }
+        }
+    }
+    /// Offered and bound leases
+    fn leases(&self) -> Vec<Lease> {
+        let mut leases: Vec<Lease> = self
+            .reservation
+            .iter()
+            .map(|(mac, client)| Lease {
+                hardware_address: mac.clone(),
+                address: client.address,
+                hostname: client.hostname.clone(),
+                state: if client.expire.is_some() { "bound" } else { "offered" },
+                expire: client
+                    .expire
+                    .and_then(|expire| expire.duration_since(UNIX_EPOCH).ok())
+                    .map(|expire| expire.as_secs()),
+            })
+            .collect();
+        leases.sort_by_key(|lease| lease.address);
+        leases
+    }
+    /// Size and used addresses of every range
+    fn utilisation(&self) -> Vec<SubnetUsage> {
+        let used = |range: &AddressRange| {
+            self.reservation
+                .values()
+                .filter(|client| range.contains(client.address))
+                .count()
+                + self.abandoned.keys().filter(|addr| range.contains(**addr)).count()
+        };
+        self.configuration
+            .subnets
+            .iter()
+            .map(|subnet| {
+                let ranges: Vec<RangeUsage> = subnet
+                    .ranges
+                    .iter()
+                    .map(|range| RangeUsage {
+                        start_address: range.start_address,
+                        end_address: range.end_address,
+                        size: Pool::size(&[range]),
+                        used: used(range),
+                    })
+                    .collect();
+                SubnetUsage {
+                    network: subnet.network,
+                    subnet_mask: subnet.subnet_mask,
+                    size: ranges.iter().map(|range| range.size).sum(),
+                    used: ranges.iter().map(|range| range.used).sum(),
+                    ranges,
+                }
+            })
+            .collect()
+    }
+    /// Index of the subnet an address belongs to
+    fn subnet_of(&self, addr: Ipv4Addr) -> std::option::Option<usize> {
+        self.configuration
+            .subnets
+            .iter()
+            .position(|subnet| subnet.contains(addr))
+    }
+    /// Switch to a new configuration without losing the leases. Offers
+    /// outside of the new ranges are dropped, bound leases are kept until the
+    /// client renews and returned so they can be reported.
+    fn reload(&mut self, configuration: Configuration) -> Result<Vec<Lease>, String> {
+        let problems = configuration.validate();
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+            return Err(problems.join(", "));
+        }
+        let sections = |c: &Configuration| {
+            [
+                ("listen", serde_json::to_value(c.listen)),
+                ("tftp", serde_json::to_value(&c.tftp)),
+                ("ddns", serde_json::to_value(&c.ddns)),
+                ("leases_file", serde_json::to_value(&c.leases_file)),
+                ("failover", serde_json::to_value(&c.failover)),
+                ("api", serde_json::to_value(&c.api)),
+                ("metrics", serde_json::to_value(&c.metrics)),
+                ("capture", serde_json::to_value(&c.capture)),
+            ]
+        };
+        for ((section, old), (_, new)) in sections(&self.configuration)
+            .into_iter()
+            .zip(sections(&configuration))
+        {
+            if old.ok() != new.ok() {
+                warn!(section, "configuration change ignored until the server restarts");
+            }
+        }
+        if serde_json::to_value(&self.configuration.conflict_detection).ok()
+            != serde_json::to_value(&configuration.conflict_detection).ok()
+        {
+            self.prober = prober_for(&configuration.conflict_detection);
+        }
+        // Sections only read at startup keep their current value
+        let configuration = Configuration {
+            listen: self.configuration.listen,
+            tftp: self.configuration.tftp.take(),
+            ddns: self.configuration.ddns.take(),
+            leases_file: self.configuration.leases_file.take(),
+            failover: self.configuration.failover.take(),
+            api: self.configuration.api.take(),
+            metrics: self.configuration.metrics.take(),
+            capture: self.configuration.capture.take(),
+            ..configuration
+        };
+        self.configuration = configuration;
+
+        let invalid: Vec<(String, bool)> = self
+            .reservation
+            .iter()
+            .filter(|(mac, client)| !self.configuration.is_valid_lease(mac, client.address))
+            .map(|(mac, client)| (mac.clone(), client.expire.is_some()))
+            .collect();
+        let mut flagged: Vec<String> = Vec::new();
+        for (mac, bound) in invalid {
+            if bound {
+                flagged.push(mac);
+            } else if let Some(client) = self.reservation.remove(&mac) {
+                self.record(&mac, "expired", client.address);
+            }
+        }
+        let configuration = &self.configuration;
+        self.abandoned.retain(|addr, _| {
+            configuration
+                .subnets
+                .iter()
+                .any(|subnet| subnet.ranges.iter().any(|range| range.contains(*addr)))
+        });
+        self.dump_leases();
+        let flagged: Vec<Lease> = self
+            .leases()
+            .into_iter()
+            .filter(|lease| flagged.contains(&lease.hardware_address))
+            .collect();
+        for lease in flagged.iter() {
+            warn!(
+                address = %lease.address,
+                chaddr = %lease.hardware_address,
+                "lease outside of the configured ranges, refused at renewal"
+            );
+        }
+        Ok(flagged)
+    }
 }
 
 pub struct DhcpServer {
-    socket: UdpSocket,
-    configuration_path: String,
+    socket: Box<dyn Transport>,
+    metrics: Arc<Registry>,
+    capture: Mutex<std::option::Option<PacketCapture>>,
 }
 impl DhcpServer {
-    pub fn new(configuration_path: String) -> DhcpServer {
+    pub fn new(listen: SocketAddr) -> DhcpServer {
+        let socket = UdpSocket::bind(listen).expect("ERR: unable to bind the DHCP address");
+        // Wake up regularly to expire the leases
+        socket
+            .set_read_timeout(Some(HOUSEKEEPING_INTERVAL))
+            .expect("ERR: unable to set the socket timeout");
+        DhcpServer::with_transport(Box::new(socket))
+    }
+    pub fn with_transport(socket: Box<dyn Transport>) -> DhcpServer {
         DhcpServer {
-            socket: UdpSocket::bind("127.0.0.1:67").expect("ERR: unable to bind the port 67"),
-            configuration_path
+            socket,
+            metrics: Arc::new(Registry::default()),
+            capture: Mutex::new(None),
         }
     }
-    pub fn on_recv(&self) {
-        let mut pool: Pool = Pool::new(Configuration::new(AddressRange::new(
-            Ipv4Addr::new(192, 168, 0, 1),
-            Ipv4Addr::new(192, 168, 0, 10),
-            Ipv4Addr::new(255, 255, 255, 0),
-        ), self.configuration_path));
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    pub fn on_recv(&self, configuration: Configuration) {
+        if let Some(tftp) = &configuration.tftp {
+            let tftp_server = TftpServer::new(tftp).expect("ERR: unable to bind the TFTP port");
+            info!(root = %tftp.root, address = %tftp.address, "TFTP server started");
+            thread::spawn(move || tftp_server.on_recv());
+        }
+        let ddns: std::option::Option<DnsUpdater> = configuration.ddns.clone().map(DnsUpdater::new);
+        // Updates for the failover peer and events coming from it
+        let (failover_tx, failover_rx): (
+            std::option::Option<Sender<Update>>,
+            std::option::Option<Receiver<Event>>,
+        ) = match configuration.failover.clone() {
+            Some(failover) => {
+                let (updates_tx, updates_rx) = mpsc::channel();
+                let (events_tx, events_rx) = mpsc::channel();
+                info!(role = ?failover.role, address = %failover.address, "failover enabled");
+                failover::start(failover, updates_rx, events_tx)
+                    .expect("ERR: unable to bind the failover address");
+                (Some(updates_tx), Some(events_rx))
+            }
+            None => (None, None),
+        };
+        let (api_tx, api_rx) = mpsc::channel::<Command>();
+        if let Some(api) = &configuration.api {
+            let api_server = ApiServer::new(api).expect("ERR: unable to start the API");
+            info!(address = %api.address, "management API started");
+            let api_tx = api_tx.clone();
+            thread::spawn(move || api_server.on_recv(api_tx));
+        }
+        if let Some(metrics) = &configuration.metrics {
+            let metrics_server =
+                MetricsServer::new(metrics).expect("ERR: unable to bind the metrics address");
+            info!(address = %metrics.address, "metrics endpoint started");
+            let registry = self.metrics.clone();
+            let api_tx = api_tx.clone();
+            thread::spawn(move || metrics_server.on_recv(&registry, api_tx));
+        }
+        if let Some(capture) = &configuration.capture {
+            let packet_capture =
+                PacketCapture::new(capture).expect("ERR: unable to create the capture file");
+            info!(path = %capture.path, "capturing the DHCP packets");
+            *self.capture.lock().unwrap() = Some(packet_capture);
+        }
+        let mut pool: Pool = Pool::new(configuration);
+        pool.metrics = self.metrics.clone();
+        let reload_requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, reload_requested.clone())
+            .expect("ERR: unable to handle SIGHUP");
 
+        let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
         loop {
-            let mut buffer = [0; 576];
-            let (num_byte, src_addr) = self
-                .socket
-                .recv_from(&mut buffer)
-                .expect("ERR: An error occured while receiving bytes");
+            if let (Some(events), Some(peer)) = (&failover_rx, &failover_tx) {
+                while let Ok(event) = events.try_recv() {
+                    pool.on_failover_event(event, peer);
+                }
+            }
+            if reload_requested.swap(false, Ordering::Relaxed) {
+                match reload(&mut pool) {
+                    Ok(flagged) => {
+                        info!(outside_ranges = flagged.len(), "configuration reloaded on SIGHUP")
+                    }
+                    Err(e) => error!(error = %e, "configuration not reloaded"),
+                }
+            }
+            while let Ok(command) = api_rx.try_recv() {
+                let reply = on_api_request(&mut pool, &ddns, &failover_tx, command.request);
+                let _ = command.reply.send(reply);
+            }
 
-            //println!("Pretty hex : {}", pretty_hex(&buffer));
-            let mut msg: Message = Message::deserialize(buffer.to_vec());
+            let expired = pool.expire();
+            if !expired.is_empty() {
+                pool.dump_leases();
+            }
+            for (mac, client) in expired {
+                info!(address = %client.address, hostname = %client.hostname, "lease expired");
+                if let (Some(ddns), false) = (&ddns, client.hostname.is_empty()) {
+                    ddns.remove(&mac, &client.hostname, client.address, client.updates);
+                }
+            }
 
-            println!("DEBUG: Message received : {}", &msg);
+            let (num_byte, src_addr) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut
+                        || e.kind() == std::io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotConnected => {
+                    info!("transport closed, server stopped");
+                    return;
+                }
+                // ICMP errors of a previous send (ECONNREFUSED), full
+                // buffers... only this packet is lost
+                Err(e) => {
+                    warn!(error = %e, "unable to receive a packet");
+                    continue;
+                }
+            };
 
-            let dhcp_type: MessageType = msg
-                .options
-                .options
-                .iter()
-                .find(|e| e.op_code == 53)
-                .expect(
-                    "DHCP Message MUST have a type field : msg did not contain 53 code in option field",
-                    )
-                .data[0]
-                .into();
+            if let (Some(capture), Ok(local_addr)) =
+                (self.capture.lock().unwrap().as_mut(), self.socket.local_addr())
+            {
+                let server_identifier = self.server_identifier(&pool.configuration);
+                capture.received(&buffer[..num_byte], src_addr, local_addr, server_identifier);
+            }
+
+            //println!("Pretty hex : {}", pretty_hex(&buffer));
+            let packet: MessageRef = match MessageRef::new(&buffer[..num_byte]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    warn!(source = %src_addr, error = %e, "invalid DHCP message");
+                    self.metrics.parse_failure();
+                    continue;
+                }
+            };
+
+            // DHCP Message MUST have a type field
+            let dhcp_type: MessageType = match packet.message_type() {
+                Some(message_type) => message_type,
+                None => {
+                    warn!(source = %src_addr, "message without a valid type, ignored");
+                    self.metrics.parse_failure();
+                    continue;
+                }
+            };
+            self.metrics.received(&dhcp_type);
+            let span = info_span!(
+                "message",
+                xid = %format_args!("{:08x}", packet.xid()),
+                chaddr = %packet.mac_address(),
+                message_type = ?dhcp_type,
+            );
+            let _entered = span.enter();
+            debug!(source = %src_addr, giaddr = %packet.giaddr(), ciaddr = %packet.ciaddr(), "message received");
+            if let Some(balancing) = &pool.configuration.load_balancing {
+                if !balancing.accepts(&packet) {
+                    debug!("client served by another server, ignored");
+                    continue;
+                }
+            }
+            // Only the messages which are answered are copied
+            let msg: Message = packet.to_message();
+            trace!("message received : {}", &msg);
+            let server_identifier = self.server_identifier(&pool.configuration);
 
             match dhcp_type {
                 MessageType::DHCPDISCOVER => {
                     // Server should respond with a DHCPOFFER message
-                    let mac: String = String::from_utf8_lossy(&msg.chaddr).to_string();
-                    let client_offer: Client = match pool.reserve_ip(mac) {
-                        Ok(client) => client.clone(),
+                    let mac: String = msg.mac_address();
+                    let classes: Vec<String> = pool.configuration.classify(&msg);
+                    let subnet = match pool.configuration.subnet_for(msg.giaddr) {
+                        Some(subnet) if pool.configuration.subnets[subnet].allows(&classes) => {
+                            subnet
+                        }
+                        _ => {
+                            warn!(giaddr = %msg.giaddr, "no subnet configured for the client, ignored");
+                            continue;
+                        }
+                    };
+                    let started = Instant::now();
+                    let reserved = pool.reserve_ip(mac.clone(), subnet, &classes).cloned();
+                    self.metrics.allocation(started.elapsed());
+                    let client_offer: Client = match reserved {
+                        Ok(client) => client,
                         Err(e) => {
-                            println!("Error type : {:?}", e);
-                            println!("Unable to reserve an IP : skipping.");
-                            return;
+                            warn!(subnet = %pool.configuration.subnets[subnet].network, error = ?e, "unable to reserve an address");
+                            continue;
                         }
                     };
+                    let hostname: String = pool.hostname_for(&msg, subnet);
+                    if let Some(client) = pool.reservation.get_mut(&mac) {
+                        if client.expire.is_none() {
+                            client.hostname = hostname;
+                        }
+                    }
+                    let lease_time = pool.configuration.subnets[subnet].lease_for(&mac).clone();
+                    let lease = lease_time.negotiate(requested_lease(&msg));
                     let yiaddr = client_offer.address;
-                    self.send_offer(&msg, src_addr, yiaddr);
+                    let mut offer = with_lease(
+                        self.reply(&msg, MessageType::DHCPOFFER, server_identifier).set_yiaddr(yiaddr),
+                        &lease_time,
+                        lease,
+                    );
+                    match pool.configuration.options_for(subnet, &classes) {
+                        Ok(configured) => offer = offer.extend_options(configured),
+                        Err(e) => warn!(error = %e, "unable to encode the options"),
+                    }
+                    let boot = pool.configuration.subnets[subnet].boot.as_ref();
+                    let tftp = pool.configuration.tftp.as_ref();
+                    if let Err(e) = self.send_reply(&msg, offer, src_addr, boot, tftp) {
+                        warn!(destination = %src_addr, error = %e, "unable to send the reply");
+                    }
+                    info!(%yiaddr, subnet = %pool.configuration.subnets[subnet].network, "address offered");
+                    pool.record(&mac, "offered", yiaddr);
                 }
                 MessageType::DHCPREQUEST => {
                     // Server should respond with a DHCPACK message
+                    let mac: String = msg.mac_address();
+                    if let Some(selected) = msg.option(Option::ServerIdentifier) {
+                        if selected.data != server_identifier.octets() {
+                            // The client accepted an offer from another server
+                            if pool.reservation.get(&mac).is_some_and(|c| c.expire.is_none()) {
+                                pool.reservation.remove(&mac);
+                            }
+                            continue;
+                        }
+                    }
+                    let requested: Ipv4Addr = match msg.option(Option::RequestedIPAddress) {
+                        Some(op) if op.data.len() == 4 => {
+                            Ipv4Addr::new(op.data[0], op.data[1], op.data[2], op.data[3])
+                        }
+                        _ => msg.ciaddr,
+                    };
+                    let client: Client = match pool.reservation.get(&mac) {
+                        Some(client) => client.clone(),
+                        // No record of this client, RFC 2131 says we must remain silent
+                        None => continue,
+                    };
+                    let valid = pool.configuration.is_valid_lease(&mac, client.address);
+                    let subnet = match pool.subnet_of(client.address) {
+                        Some(subnet) if client.address == requested && valid => subnet,
+                        subnet => {
+                            let (reason, message) = match subnet {
+                                Some(_) if client.address != requested => {
+                                    ("wrong_address", "requested address is not the leased one")
+                                }
+                                Some(_) => ("outside_range", "address is no longer available"),
+                                None => ("unknown_subnet", "address is not on this network"),
+                            };
+                            self.metrics.nak(reason);
+                            if let Err(e) = self.send_nak(&msg, src_addr, server_identifier, message) {
+                                warn!(destination = %src_addr, error = %e, "unable to send the reply");
+                            }
+                            info!(%requested, reason, "request refused");
+                            // Left over from a previous configuration
+                            if !valid {
+                                release(&mut pool, &mac, "released", &ddns, &failover_tx);
+                            }
+                            pool.record(&mac, "refused", requested);
+                            continue;
+                        }
+                    };
+                    let classes: Vec<String> = pool.configuration.classify(&msg);
+                    let lease_time = pool.configuration.subnets[subnet].lease_for(&mac).clone();
+                    let lease = lease_time.negotiate(requested_lease(&msg));
+                    let fqdn_request: std::option::Option<ClientFqdn> = ClientFqdn::from_message(&msg);
+                    let hostname: String = pool.hostname_for(&msg, subnet);
+                    let updates: Updates = match (&ddns, &fqdn_request) {
+                        (None, _) => Updates::default(),
+                        (Some(ddns), Some(fqdn)) => fqdn.updates(ddns.client_updates()),
+                        (Some(_), None) => Updates {
+                            forward: true,
+                            reverse: true,
+                        },
+                    };
+                    if let Some(client) = pool.reservation.get_mut(&mac) {
+                        let bound = client.expire.is_some();
+                        client.lease = lease;
+                        client.expire = Some(SystemTime::now() + lease);
+                        if let Some(ddns) = &ddns {
+                            if bound && !client.hostname.is_empty() && client.hostname != hostname {
+                                ddns.remove(&mac, &client.hostname, client.address, client.updates);
+                            }
+                            let changed = client.hostname != hostname || client.updates != updates;
+                            if !hostname.is_empty() && (!bound || changed) {
+                                ddns.register(&mac, &hostname, client.address, updates);
+                            }
+                        }
+                        client.hostname = hostname.clone();
+                        client.updates = updates;
+                    }
+                    pool.dump_leases();
+                    if let (Some(peer), Some(update)) = (&failover_tx, pool.binding(&mac)) {
+                        let _ = peer.send(update);
+                    }
+                    let mut ack = with_lease(
+                        self.reply(&msg, MessageType::DHCPACK, server_identifier).set_yiaddr(client.address),
+                        &lease_time,
+                        lease,
+                    );
+                    if let Some(fqdn_request) = &fqdn_request {
+                        let fqdn = match &ddns {
+                            Some(ddns) if !hostname.is_empty() => ddns.fqdn(&hostname),
+                            _ => hostname.clone(),
+                        };
+                        ack = ack.extend_options(fqdn_request.reply(&fqdn, updates));
+                    }
+                    match pool.configuration.options_for(subnet, &classes) {
+                        Ok(configured) => ack = ack.extend_options(configured),
+                        Err(e) => warn!(error = %e, "unable to encode the options"),
+                    }
+                    let boot = pool.configuration.subnets[subnet].boot.as_ref();
+                    let tftp = pool.configuration.tftp.as_ref();
+                    if let Err(e) = self.send_reply(&msg, ack, src_addr, boot, tftp) {
+                        warn!(destination = %src_addr, error = %e, "unable to send the reply");
+                    }
+                    info!(
+                        yiaddr = %client.address,
+                        subnet = %pool.configuration.subnets[subnet].network,
+                        lease = lease.as_secs(),
+                        %hostname,
+                        "lease acknowledged"
+                    );
+                    pool.record(&mac, "bound", client.address);
                 }
                 MessageType::DHCPRELEASE => {
                     // Release address
+                    let mac: String = msg.mac_address();
+                    match pool.reservation.get(&mac) {
+                        Some(client) if client.address == msg.ciaddr => {}
+                        _ => continue,
+                    }
+                    if let Some(client) = release(&mut pool, &mac, "released", &ddns, &failover_tx) {
+                        info!(yiaddr = %client.address, "lease released");
+                    }
+                }
+                MessageType::DHCPDECLINE => {
+                    // The client found the address in use, it is not given
+                    // again, RFC 2131 section 4.3.3
+                    let mac: String = msg.mac_address();
+                    if let Some(selected) = msg.option(Option::ServerIdentifier) {
+                        if selected.data != server_identifier.octets() {
+                            continue;
+                        }
+                    }
+                    let declined = match (msg.option(Option::RequestedIPAddress), pool.reservation.get(&mac)) {
+                        (Some(op), Some(client)) if op.data == client.address.octets() => client.address,
+                        _ => continue,
+                    };
+                    release(&mut pool, &mac, "declined", &ddns, &failover_tx);
+                    pool.abandoned.insert(declined, Instant::now());
+                    warn!(address = %declined, "address declined by the client, marking it as abandoned");
+                }
+                MessageType::DHCPINFORM => {
+                    // The client configured its address itself and only
+                    // asks for the other parameters, RFC 2131 section 4.3.5
+                    let classes: Vec<String> = pool.configuration.classify(&msg);
+                    let subnet = match pool.subnet_of(msg.ciaddr) {
+                        Some(subnet) if pool.configuration.subnets[subnet].allows(&classes) => {
+                            subnet
+                        }
+                        _ => {
+                            warn!(ciaddr = %msg.ciaddr, "no subnet configured for the client, ignored");
+                            continue;
+                        }
+                    };
+                    let mut ack = self.reply(&msg, MessageType::DHCPACK, server_identifier);
+                    match pool.configuration.options_for(subnet, &classes) {
+                        Ok(configured) => ack = ack.extend_options(configured),
+                        Err(e) => warn!(error = %e, "unable to encode the options"),
+                    }
+                    let boot = pool.configuration.subnets[subnet].boot.as_ref();
+                    let tftp = pool.configuration.tftp.as_ref();
+                    if let Err(e) = self.send_reply(&msg, ack, src_addr, boot, tftp) {
+                        warn!(destination = %src_addr, error = %e, "unable to send the reply");
+                    }
+                    info!(subnet = %pool.configuration.subnets[subnet].network, "parameters given");
+                }
+                MessageType::DHCPOFFER | MessageType::DHCPACK | MessageType::DHCPNAK => {
+                    debug!("message sent by a server, ignored");
                 }
-                _ => todo!(),
             }
         }
     }
-    fn send<T>(&self, message: Message, dest: T) -> Result<usize, std::io::Error>
-    where
-        T: std::net::ToSocketAddrs,
-    {
-        self.socket.send_to(&message.serialize(), dest)
+    /// Send a reply, without the options which don't fit in the maximum
+    /// message size of the client
+    fn send(&self, request: &Message, mut message: Message, dest: SocketAddr) -> Result<usize, std::io::Error> {
+        let max_length = max_reply_length(request);
+        if message.length() > max_length {
+            // The options the client asked for are the last ones dropped
+            let requested: Vec<u8> = request
+                .option(Option::ParameterRequestList)
+                .map(|op| op.data.clone())
+                .unwrap_or_default();
+            let essential = [
+                Option::DHCPMessageType as u8,
+                Option::ServerIdentifier as u8,
+                Option::IPAddressLeaseTime as u8,
+            ];
+            message.options.options.sort_by_key(|option| {
+                if essential.contains(&option.op_code) {
+                    0
+                } else if requested.contains(&option.op_code) {
+                    1
+                } else {
+                    2
+                }
+            });
+            let dropped = message.truncate(max_length);
+            let codes: Vec<u8> = dropped.iter().map(|option| option.op_code).collect();
+            warn!(?codes, "options dropped to fit the maximum message size of the client");
+        }
+        if let Some(message_type) = message.message_type() {
+            self.metrics.sent(&message_type);
+        }
+        let bytes = message.serialize();
+        let sent = self.socket.send_to(&bytes, dest)?;
+        if let (Some(capture), Ok(local_addr)) =
+            (self.capture.lock().unwrap().as_mut(), self.socket.local_addr())
+        {
+            capture.sent(&bytes, local_addr, dest);
+        }
+        Ok(sent)
+    }
+    /// Reply to a client message, every reply carries the server identifier
+    fn reply(
+        &self,
+        request: &Message,
+        message_type: MessageType,
+        server_identifier: Ipv4Addr,
+    ) -> Message {
+        Message::reply_to(request, message_type).set_server_identifier(server_identifier)
     }
-    fn send_offer<T>(
+    /// Send a DHCPOFFER or a DHCPACK, with the boot parameters of the subnet
+    fn send_reply(
         &self,
-        source: &Message,
-        dest: T,
-        yiaddr: Ipv4Addr,
-    ) -> Result<usize, std::io::Error>
-    where
-        T: std::net::ToSocketAddrs,
-    {
-        /**
-         * Field      DHCPOFFER
-         * -----      ---------            
-         * 'op'       BOOTREPLY           
-         * 'htype'    (From "Assigned Numbers" RFC)
-         * 'hlen'     (Hardware address length in octets)
-         * 'hops'     0                    
-         * 'xid'      'xid' from client DHCPDISCOVER message             
-         * 'secs'     0                    
-         * 'ciaddr'   0                    
-         * 'yiaddr'   IP address offered to client            
-         * 'siaddr'   IP address of next bootstrap server    
-         * 'flags'    'flags' from client DHCPDISCOVER message              
-         * 'giaddr'   'giaddr' from client DHCPDISCOVER message              
-         * 'chaddr'   'chaddr' from client DHCPDISCOVER message             
-         * 'sname'    Server host name or options           
-         * 'file'     Client boot file name or options      
-         * 'options'  options         
-         */
-        // Still to do but try if pooling works
-        //let yiaddr: Ipv4Addr = todo!();
-        //let siaddr: Ipv4Addr = todo!();
-        let siaddr: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
-        let mut response: Message = Message::new(
-            OpCode::BOOTREPLY as u8,
-            source.htype,
-            source.hlen,
-            0,
-            source.xid,
-            0,
-            source.flags,
-            Ipv4Addr::new(0, 0, 0, 0),
-            yiaddr, // yiaddr
-            siaddr, // siaddr
-            source.giaddr,
-            source.chaddr,
-            [0u8; 64],
-            [0u8; 128],
-            OptionField::new(vec![]),
-        )
-        .add_options(OptionSubfield::new(Option::DHCPMessageType, vec![2]).unwrap());
-
-        println!("DEBUG: message sended : {}\n", &response);
-        // TODO : add apropriate option on the response message and use
-        self.send(response, dest)
+        request: &Message,
+        response: Message,
+        dest: SocketAddr,
+        boot: std::option::Option<&Boot>,
+        tftp: std::option::Option<&Tftp>,
+    ) -> Result<usize, std::io::Error> {
+        let response = match boot {
+            Some(boot) => boot::apply(boot, tftp, request, response),
+            None => response,
+        };
+        trace!("message sent : {}", &response);
+        self.send(request, response, dest)
+    }
+    /// A DHCPNAK only carries the message type, the server identifier and
+    /// an error message
+    fn send_nak(
+        &self,
+        request: &Message,
+        dest: SocketAddr,
+        server_identifier: Ipv4Addr,
+        message: &str,
+    ) -> Result<usize, std::io::Error> {
+        let response = self
+            .reply(request, MessageType::DHCPNAK, server_identifier)
+            .set_message(message);
+        trace!("message sent : {}", &response);
+        self.send(request, response, dest)
+    }
+    /// The configured one, or the address the socket is bound to
+    fn server_identifier(&self, configuration: &Configuration) -> Ipv4Addr {
+        match (configuration.server_identifier, self.socket.local_addr().map(|addr| addr.ip())) {
+            (Some(identifier), _) => identifier,
+            (None, Ok(IpAddr::V4(addr))) => addr,
+            _ => Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+fn prober_for(conflict_detection: &ConflictDetection) -> Box<dyn Prober> {
+    if conflict_detection.enabled {
+        Box::new(IcmpProber::new())
+    } else {
+        Box::new(NoProber)
+    }
+}
+
+/// Remove the lease of a client, with its DNS records
+fn release(
+    pool: &mut Pool,
+    mac: &str,
+    event: &'static str,
+    ddns: &std::option::Option<DnsUpdater>,
+    peer: &std::option::Option<Sender<Update>>,
+) -> std::option::Option<Client> {
+    let client = pool.reservation.remove(mac)?;
+    pool.record(mac, event, client.address);
+    pool.dump_leases();
+    if let Some(peer) = peer {
+        let _ = peer.send(Update::Release(mac.to_string(), client.address));
+    }
+    if let (Some(ddns), false) = (ddns, client.hostname.is_empty()) {
+        ddns.remove(mac, &client.hostname, client.address, client.updates);
+    }
+    Some(client)
+}
+
+/// Read the configuration file again and apply it to the pool
+fn reload(pool: &mut Pool) -> Result<Vec<Lease>, String> {
+    let path = pool.configuration.configuration_path.clone();
+    let configuration = Configuration::read(path)?;
+    pool.reload(configuration)
+}
+
+fn on_api_request(
+    pool: &mut Pool,
+    ddns: &std::option::Option<DnsUpdater>,
+    peer: &std::option::Option<Sender<Update>>,
+    request: api::Request,
+) -> Reply {
+    let to_json = |value: serde_json::Result<serde_json::Value>| value.map_err(|e| (500, e.to_string()));
+    match request {
+        api::Request::Leases(filter) => {
+            let leases: Vec<Lease> = pool
+                .leases()
+                .into_iter()
+                .filter(|lease| filter.matches(lease))
+                .collect();
+            to_json(serde_json::to_value(leases))
+        }
+        api::Request::Release(address) => {
+            let mac = pool
+                .reservation
+                .iter()
+                .find(|(_, client)| client.address == address)
+                .map(|(mac, _)| mac.clone())
+                .ok_or((404, format!("no lease for {}", address)))?;
+            release(pool, &mac, "released", ddns, peer);
+            info!(%address, chaddr = %mac, "lease released by the API");
+            Ok(serde_json::json!({ "hardware_address": mac, "address": address }))
+        }
+        api::Request::History(mac) => match pool.history.get(&mac) {
+            Some(history) => to_json(serde_json::to_value(history)),
+            None => Err((404, format!("no history for {}", mac))),
+        },
+        api::Request::Pools => to_json(serde_json::to_value(pool.utilisation())),
+        api::Request::Reservations => {
+            let reservations: Vec<Reservation> = pool
+                .configuration
+                .subnets
+                .iter()
+                .flat_map(|subnet| {
+                    subnet.hosts.iter().map(|host| Reservation {
+                        subnet: subnet.network,
+                        host: host.clone(),
+                    })
+                })
+                .collect();
+            to_json(serde_json::to_value(reservations))
+        }
+        api::Request::AddReservation(reservation) => {
+            let mac = reservation.host.hardware_address.to_ascii_lowercase();
+            let subnets = &pool.configuration.subnets;
+            if subnets.iter().any(|subnet| subnet.host(&mac).is_some()) {
+                return Err((409, format!("{} already has a reservation", mac)));
+            }
+            let index = subnets
+                .iter()
+                .position(|subnet| subnet.network == reservation.subnet)
+                .ok_or((404, format!("no subnet {}", reservation.subnet)))?;
+            if let Some((_, message)) = reservation.host.lease.iter().flat_map(LeaseTime::problems).next() {
+                return Err((400, message));
+            }
+            if let Some(address) = reservation.host.fixed_address {
+                if !subnets[index].contains(address) {
+                    return Err((400, format!("{} is not in {}", address, reservation.subnet)));
+                }
+                if pool.reservation.iter().any(|(other, client)| *other != mac && client.address == address) {
+                    return Err((409, format!("{} is leased to another client", address)));
+                }
+            }
+            let host = Host {
+                hardware_address: mac.clone(),
+                ..reservation.host
+            };
+            pool.configuration.subnets[index].hosts.push(host);
+            // A pending offer would hide the fixed address
+            if pool.reservation.get(&mac).is_some_and(|client| client.expire.is_none()) {
+                pool.reservation.remove(&mac);
+            }
+            info!(chaddr = %mac, "reservation added by the API");
+            Ok(serde_json::json!({ "hardware_address": mac }))
+        }
+        api::Request::RemoveReservation(mac) => {
+            let mut removed = false;
+            for subnet in pool.configuration.subnets.iter_mut() {
+                let before = subnet.hosts.len();
+                subnet
+                    .hosts
+                    .retain(|host| !host.hardware_address.eq_ignore_ascii_case(&mac));
+                removed |= subnet.hosts.len() != before;
+            }
+            if !removed {
+                return Err((404, format!("no reservation for {}", mac)));
+            }
+            info!(chaddr = %mac, "reservation removed by the API");
+            Ok(serde_json::json!({ "hardware_address": mac }))
+        }
+        api::Request::Reload => {
+            let flagged = reload(pool).map_err(|e| (400, e))?;
+            info!("configuration reloaded by the API");
+            Ok(serde_json::json!({ "reloaded": true, "outside_ranges": flagged }))
+        }
+        api::Request::PartnerDown => {
+            let failover = pool
+                .failover
+                .as_mut()
+                .ok_or((404, "failover is not configured".to_string()))?;
+            failover.partner_down().map_err(|e| (409, e))?;
+            info!("failover peer confirmed down by the API");
+            Ok(serde_json::json!({ "state": "partner-down" }))
+        }
+    }
+}
+
+/// Sanitized hostname of the client, the domain name of option 81 is
+/// preferred over option 12
+fn client_hostname(msg: &Message) -> std::option::Option<String> {
+    let name: String = match ClientFqdn::from_message(msg) {
+        Some(fqdn) if !fqdn.name.is_empty() => fqdn.name,
+        _ => String::from_utf8_lossy(&msg.option(Option::HostName)?.data).to_string(),
+    };
+    Some(fqdn::sanitize(&name)).filter(|hostname| !hostname.is_empty())
+}
+
+/// Lease time requested by the client with option 51
+fn requested_lease(msg: &Message) -> std::option::Option<Duration> {
+    let op = msg.option(Option::IPAddressLeaseTime)?;
+    let seconds: [u8; 4] = op.data.as_slice().try_into().ok()?;
+    Some(Duration::from_secs(u32::from_be_bytes(seconds).into()))
+}
+
+/// Largest reply the client accepts : 576 bytes datagrams, or the maximum
+/// DHCP message size of option 57, without the IP and UDP headers
+fn max_reply_length(request: &Message) -> usize {
+    let datagram = match request.option(Option::MaximumDHCPMessageSize) {
+        Some(op) if op.data.len() == 2 => {
+            usize::from(u16::from_be_bytes([op.data[0], op.data[1]])).max(MINIMUM_DATAGRAM)
+        }
+        _ => MINIMUM_DATAGRAM,
+    };
+    datagram.min(MAX_UDP_PAYLOAD + IP_UDP_HEADERS) - IP_UDP_HEADERS
+}
+
+/// Options 51, 58 and 59 for a lease
+fn with_lease(response: Message, lease_time: &LeaseTime, lease: Duration) -> Message {
+    response
+        .set_lease_time(lease)
+        .set_renewal_time(lease_time.renewal_time(lease))
+        .set_rebinding_time(lease_time.rebinding_time(lease))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIGURATION: &str = r#"
+server_identifier = "192.0.2.1"
+
+[[subnet]]
+network = "192.0.2.0"
+subnet_mask = "255.255.255.0"
+append_domain = true
+
+[subnet.options]
+domain-name = "Example.COM"
+
+[[subnet.range]]
+start_address = "192.0.2.10"
+end_address = "192.0.2.19"
+"#;
+
+    /// Range removed by the reload
+    const REMOVED_RANGE: &str = r#"
+[[subnet.range]]
+start_address = "192.0.2.50"
+end_address = "192.0.2.59"
+"#;
+
+    /// DHCPREQUEST of `chaddr` with the host name option
+    fn request(chaddr: [u8; 6], hostname: &str) -> Message {
+        let mut packet = vec![0u8; 236];
+        packet[..3].copy_from_slice(&[1, 1, 6]);
+        packet[28..34].copy_from_slice(&chaddr);
+        packet.extend_from_slice(&MAGIC_COOKIE);
+        packet.extend_from_slice(&[Option::DHCPMessageType as u8, 1, 3]);
+        packet.extend_from_slice(&[Option::HostName as u8, hostname.len() as u8]);
+        packet.extend_from_slice(hostname.as_bytes());
+        packet.push(255);
+        Message::deserialize(&packet).unwrap()
+    }
+
+    #[test]
+    fn hostname_dedup() {
+        let configuration = toml::from_str(CONFIGURATION).unwrap();
+        let mut pool = Pool::with_prober(configuration, Box::new(NoProber));
+        let first = request([0, 0x11, 0x22, 0x33, 0x44, 0x55], "Laptop");
+        assert_eq!(pool.hostname_for(&first, 0), "laptop.example.com");
+        pool.reservation.insert(
+            first.mac_address(),
+            Client {
+                hostname: "laptop.example.com".to_string(),
+                ..Client::init(Ipv4Addr::new(192, 0, 2, 10))
+            },
+        );
+        // The holder keeps its name
+        assert_eq!(pool.hostname_for(&first, 0), "laptop.example.com");
+        // Another client gets the end of its hardware address appended
+        let second = request([0, 0x66, 0x77, 0x88, 0x99, 0xaa], "laptop");
+        assert_eq!(pool.hostname_for(&second, 0), "laptop-8899aa.example.com");
+        // Fully qualified names are kept as sent
+        let qualified = request([0, 0x66, 0x77, 0x88, 0x99, 0xab], "desk.lan");
+        assert_eq!(pool.hostname_for(&qualified, 0), "desk.lan");
+    }
+
+    #[test]
+    fn reload_keeps_the_valid_leases() {
+        let before = format!("{}{}", CONFIGURATION, REMOVED_RANGE);
+        let mut pool = Pool::with_prober(toml::from_str(&before).unwrap(), Box::new(NoProber));
+        let bound = |address| Client {
+            expire: Some(SystemTime::now() + Duration::from_secs(3600)),
+            ..Client::init(address)
+        };
+        let moved = "00:00:00:00:00:01".to_string();
+        let kept = "00:00:00:00:00:02".to_string();
+        let offered = "00:00:00:00:00:03".to_string();
+        pool.reservation
+            .insert(moved.clone(), bound(Ipv4Addr::new(192, 0, 2, 50)));
+        pool.reservation
+            .insert(kept.clone(), bound(Ipv4Addr::new(192, 0, 2, 10)));
+        pool.reservation
+            .insert(offered.clone(), Client::init(Ipv4Addr::new(192, 0, 2, 51)));
+
+        let flagged = pool.reload(toml::from_str(CONFIGURATION).unwrap()).unwrap();
+        // The lease of the removed range is reported and kept until renewal
+        let flagged: Vec<&str> = flagged
+            .iter()
+            .map(|lease| lease.hardware_address.as_str())
+            .collect();
+        assert_eq!(flagged, [moved.as_str()]);
+        assert!(pool.reservation.contains_key(&moved));
+        assert!(pool.reservation.contains_key(&kept));
+        // The offer is dropped
+        assert!(!pool.reservation.contains_key(&offered));
+        let events: Vec<&str> = pool.history[&offered]
+            .iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(events, ["expired"]);
     }
 }
@@ -0,0 +1,362 @@
+//! Read only TFTP server (RFC 1350) used to serve boot files, with the
+//! blksize (RFC 2348) and tsize (RFC 2349) options.
+//!
+//! Each transfer is done from its own socket and thread, as the RFC asks for
+//! a new transfer identifier per connection. Files requested in netascii
+//! mode have their line endings converted to CR LF.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Bytes, Read};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::configuration::Tftp;
+
+const RRQ: u16 = 1;
+const WRQ: u16 = 2;
+const DATA: u16 = 3;
+const ACK: u16 = 4;
+const ERROR: u16 = 5;
+const OACK: u16 = 6;
+
+const ERR_NOT_DEFINED: u16 = 0;
+const ERR_FILE_NOT_FOUND: u16 = 1;
+const ERR_ACCESS_VIOLATION: u16 = 2;
+const ERR_ILLEGAL_OPERATION: u16 = 4;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const MIN_BLOCK_SIZE: usize = 8;
+const MAX_BLOCK_SIZE: usize = 65464;
+const TIMEOUT: Duration = Duration::from_secs(1);
+const RETRIES: usize = 5;
+// Largest UDP payload, requests carrying many options are not truncated
+const MAX_REQUEST: usize = 65507;
+// Transfers running at the same time, the next requests are refused
+const MAX_TRANSFERS: usize = 64;
+
+pub struct TftpServer {
+    socket: UdpSocket,
+    root: PathBuf,
+    /// Transfers running
+    transfers: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct ReadRequest {
+    filename: String,
+    mode: String,
+    options: Vec<(String, String)>,
+}
+
+impl TftpServer {
+    pub fn new(configuration: &Tftp) -> io::Result<TftpServer> {
+        Ok(TftpServer {
+            socket: UdpSocket::bind(configuration.address)?,
+            root: PathBuf::from(&configuration.root),
+            transfers: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    pub fn on_recv(&self) {
+        let mut buffer = vec![0u8; MAX_REQUEST];
+        loop {
+            let (num_byte, src_addr) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!(error = %e, "TFTP unable to receive bytes");
+                    continue;
+                }
+            };
+            let packet = &buffer[..num_byte];
+            let opcode = match packet {
+                [high, low, ..] => u16::from_be_bytes([*high, *low]),
+                _ => continue,
+            };
+            match opcode {
+                RRQ => {}
+                WRQ => {
+                    send_error(&self.socket, src_addr, ERR_ACCESS_VIOLATION, "read only server");
+                    continue;
+                }
+                _ => {
+                    send_error(&self.socket, src_addr, ERR_ILLEGAL_OPERATION, "expected a request");
+                    continue;
+                }
+            }
+            let request = match parse_request(&packet[2..]) {
+                Ok(request) => request,
+                Err(e) => {
+                    send_error(&self.socket, src_addr, ERR_ILLEGAL_OPERATION, &e);
+                    continue;
+                }
+            };
+            info!(client = %src_addr, filename = %request.filename, "TFTP read request");
+            let path = match resolve(&self.root, &request.filename) {
+                Some(path) => path,
+                None => {
+                    send_error(&self.socket, src_addr, ERR_FILE_NOT_FOUND, "file not found");
+                    continue;
+                }
+            };
+            let local_addr = match self.socket.local_addr() {
+                Ok(addr) => SocketAddr::new(addr.ip(), 0),
+                Err(_) => continue,
+            };
+            if self.transfers.fetch_add(1, Ordering::Relaxed) >= MAX_TRANSFERS {
+                self.transfers.fetch_sub(1, Ordering::Relaxed);
+                warn!(client = %src_addr, "too many TFTP transfers, request refused");
+                send_error(&self.socket, src_addr, ERR_NOT_DEFINED, "server busy, try again");
+                continue;
+            }
+            let transfers = self.transfers.clone();
+            thread::spawn(move || {
+                if let Err(e) = transfer(local_addr, src_addr, &path, &request) {
+                    error!(client = %src_addr, filename = %request.filename, error = %e, "TFTP transfer failed");
+                }
+                transfers.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    }
+}
+
+/// Path of a file inside the root directory, refusing anything which could
+/// escape it, symbolic links included
+pub fn resolve(root: &Path, filename: &str) -> Option<PathBuf> {
+    let relative = Path::new(filename.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let root = root.canonicalize().ok()?;
+    let path = root.join(relative).canonicalize().ok()?;
+    if path.starts_with(&root) && path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// filename, mode and options are null terminated strings
+fn parse_request(input: &[u8]) -> Result<ReadRequest, String> {
+    let mut fields = input
+        .split(|&byte| byte == 0)
+        .map(|field| String::from_utf8_lossy(field).to_string());
+    let filename = fields.next().filter(|f| !f.is_empty()).ok_or("missing filename")?;
+    let mode = fields.next().ok_or("missing mode")?.to_ascii_lowercase();
+    if mode != "octet" && mode != "netascii" {
+        return Err(format!("unsupported mode {}", mode));
+    }
+    let mut options = Vec::new();
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if !name.is_empty() {
+            options.push((name.to_ascii_lowercase(), value));
+        }
+    }
+    Ok(ReadRequest {
+        filename,
+        mode,
+        options,
+    })
+}
+
+fn transfer(
+    local_addr: SocketAddr,
+    peer: SocketAddr,
+    path: &Path,
+    request: &ReadRequest,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(local_addr)?;
+    socket.connect(peer)?;
+    let file = File::open(path)?;
+    let netascii = request.mode == "netascii";
+    let mut file: Box<dyn Read> = if netascii {
+        Box::new(Netascii::new(BufReader::new(file)))
+    } else {
+        Box::new(file)
+    };
+
+    // Options we don't know are ignored, as RFC 2347 allows
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut accepted: Vec<(String, String)> = Vec::new();
+    for (name, value) in request.options.iter() {
+        match name.as_str() {
+            "blksize" => {
+                if let Ok(requested) = value.parse::<usize>() {
+                    if requested >= MIN_BLOCK_SIZE {
+                        block_size = requested.min(MAX_BLOCK_SIZE);
+                        accepted.push((name.clone(), block_size.to_string()));
+                    }
+                }
+            }
+            "tsize" => {
+                let size = transfer_size(path, netascii)?;
+                accepted.push((name.clone(), size.to_string()));
+            }
+            _ => {}
+        }
+    }
+    if !accepted.is_empty() {
+        let mut oack: Vec<u8> = OACK.to_be_bytes().to_vec();
+        for (name, value) in accepted.iter() {
+            oack.extend_from_slice(name.as_bytes());
+            oack.push(0);
+            oack.extend_from_slice(value.as_bytes());
+            oack.push(0);
+        }
+        send_and_wait_ack(&socket, &oack, 0)?;
+    }
+
+    let mut block: u16 = 1;
+    let mut data = vec![0u8; block_size];
+    loop {
+        let len = read_block(&mut file, &mut data)?;
+        let mut packet: Vec<u8> = DATA.to_be_bytes().to_vec();
+        packet.extend_from_slice(&block.to_be_bytes());
+        packet.extend_from_slice(&data[..len]);
+        send_and_wait_ack(&socket, &packet, block)?;
+        if len < block_size {
+            return Ok(());
+        }
+        // Big files roll over to block 0, as most clients expect
+        block = block.wrapping_add(1);
+    }
+}
+
+/// Bytes sent for a file, netascii files have to be encoded to know it
+fn transfer_size(path: &Path, netascii: bool) -> io::Result<u64> {
+    if netascii {
+        Ok(Netascii::new(BufReader::new(File::open(path)?)).count() as u64)
+    } else {
+        Ok(path.metadata()?.len())
+    }
+}
+
+/// Fill the buffer unless the end of the file is reached
+fn read_block(file: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/**
+ * Netascii encoding of a file, RFC 764 : line feeds become CR LF and lone
+ * carriage returns CR NUL. Files are expected to use the local line
+ * endings, LF.
+ */
+struct Netascii<R: BufRead> {
+    bytes: Bytes<R>,
+    /// Second byte of a CR LF or CR NUL pair
+    pending: Option<u8>,
+}
+
+impl<R: BufRead> Netascii<R> {
+    fn new(reader: R) -> Self {
+        Netascii {
+            bytes: reader.bytes(),
+            pending: None,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Netascii<R> {
+    type Item = io::Result<u8>;
+    fn next(&mut self) -> Option<io::Result<u8>> {
+        if let Some(byte) = self.pending.take() {
+            return Some(Ok(byte));
+        }
+        match self.bytes.next()? {
+            Ok(b'\n') => {
+                self.pending = Some(b'\n');
+                Some(Ok(b'\r'))
+            }
+            Ok(b'\r') => {
+                self.pending = Some(0);
+                Some(Ok(b'\r'))
+            }
+            byte => Some(byte),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Netascii<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buffer.len() {
+            match self.next() {
+                Some(byte) => buffer[len] = byte?,
+                None => break,
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+fn send_and_wait_ack(socket: &UdpSocket, packet: &[u8], block: u16) -> io::Result<()> {
+    let mut buffer = [0u8; 512];
+    for _ in 0..RETRIES {
+        socket.send(packet)?;
+        // Stray packets don't give the client a longer time to answer
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => socket.set_read_timeout(Some(left))?,
+                _ => break,
+            }
+            let num_byte = match socket.recv(&mut buffer) {
+                Ok(n) => n,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            if num_byte < 4 {
+                continue;
+            }
+            let opcode = u16::from_be_bytes([buffer[0], buffer[1]]);
+            let acknowledged = u16::from_be_bytes([buffer[2], buffer[3]]);
+            match opcode {
+                ACK if acknowledged == block => return Ok(()),
+                ERROR => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "client sent an error",
+                    ))
+                }
+                // Duplicated ack of a previous block, keep waiting
+                _ => continue,
+            }
+        }
+    }
+    let _ = socket.send(&error_packet(ERR_NOT_DEFINED, "timeout"));
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no acknowledgment"))
+}
+
+fn error_packet(code: u16, message: &str) -> Vec<u8> {
+    let mut packet: Vec<u8> = ERROR.to_be_bytes().to_vec();
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+fn send_error(socket: &UdpSocket, dest: SocketAddr, code: u16, message: &str) {
+    let _ = socket.send_to(&error_packet(code, message), dest);
+}
@@ -0,0 +1,27 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// Where the DHCP packets are received from and sent to. Implemented as a
+/// trait so the server can be driven without sockets.
+///
+/// `recv_from` should give up regularly with `WouldBlock` or `TimedOut` so
+/// the leases are expired, `NotConnected` stops the server. Other errors are
+/// logged and the server reads the next packet.
+pub trait Transport: Send {
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn send_to(&self, buffer: &[u8], dest: SocketAddr) -> io::Result<usize>;
+    /// Address the packets are received on, its IP is the server identifier
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buffer)
+    }
+    fn send_to(&self, buffer: &[u8], dest: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buffer, dest)
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
@@ -0,0 +1,87 @@
+//! Load balancing, RFC 3074 : the hash of the clients, the split of the
+//! buckets between the servers, and the clients answered by every server
+//! once they have waited long enough.
+
+use dhcp_proto::{MessageRef, MAGIC_COOKIE};
+use rdhcp::balancing::{bucket, hash};
+use rdhcp::configuration::LoadBalancing;
+
+const SECS: u16 = 10;
+
+/// DHCPDISCOVER of `chaddr`, trying for `secs` seconds
+fn discover(chaddr: [u8; 6], secs: u16) -> Vec<u8> {
+    let mut packet = vec![0u8; 236];
+    packet[..3].copy_from_slice(&[1, 1, 6]);
+    packet[8..10].copy_from_slice(&secs.to_be_bytes());
+    packet[28..34].copy_from_slice(&chaddr);
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    packet.extend_from_slice(&[53, 1, 1, 255]);
+    packet
+}
+
+fn servers(count: u8) -> Vec<LoadBalancing> {
+    (0..count)
+        .map(|index| LoadBalancing {
+            servers: count,
+            index,
+            secs: SECS,
+        })
+        .collect()
+}
+
+#[test]
+fn reference_hashes() {
+    // Computed with the C function of RFC 3074 section 6
+    for (key, expected) in [
+        (&[][..], 0),
+        (&[0x00][..], 175),
+        (&[0x00, 0x01, 0x02, 0x03, 0x04, 0x05][..], 161),
+        (&[0x01, 0x00, 0x0c, 0x01, 0x02, 0x03, 0x04][..], 104),
+        (&b"rdhcp"[..], 196),
+    ] {
+        assert_eq!(hash(key), expected, "hash of {:02x?}", key);
+    }
+}
+
+#[test]
+fn mixing_table_is_a_permutation() {
+    // A single byte key is hashed into the entry 1 ^ byte of the table
+    let mut hashes: Vec<u8> = (0..=255u8).map(|byte| hash(&[byte])).collect();
+    hashes.sort();
+    assert!(hashes.iter().copied().eq(0..=255u8));
+}
+
+#[test]
+fn buckets_are_split_between_servers() {
+    for count in 1..=4 {
+        let servers = servers(count);
+        for bucket in 0..=255u8 {
+            let owners = servers.iter().filter(|server| server.owns(bucket)).count();
+            assert_eq!(owners, 1, "bucket {} with {} servers", bucket, count);
+        }
+    }
+    // Contiguous blocks, the first half for the first of two servers
+    let servers = servers(2);
+    let (first, second) = (&servers[0], &servers[1]);
+    assert!((0..128).all(|bucket| first.owns(bucket) && !second.owns(bucket)));
+    assert!((128..=255).all(|bucket| second.owns(bucket) && !first.owns(bucket)));
+}
+
+#[test]
+fn waiting_clients_are_answered() {
+    let servers = servers(2);
+    let (first, second) = (&servers[0], &servers[1]);
+    let chaddr = (0..=255u8)
+        .map(|byte| [2, 0, 0, 0, 0, byte])
+        .find(|chaddr| {
+            let packet = discover(*chaddr, 0);
+            second.owns(bucket(&MessageRef::new(&packet).unwrap()))
+        })
+        .expect("a client of the second server");
+    for (secs, answered) in [(0, false), (SECS, false), (SECS + 1, true)] {
+        let packet = discover(chaddr, secs);
+        let msg = MessageRef::new(&packet).unwrap();
+        assert_eq!(first.accepts(&msg), answered, "secs {}", secs);
+        assert!(second.accepts(&msg));
+    }
+}
@@ -0,0 +1,154 @@
+//! Network boot parameters : the boot file chosen by client architecture,
+//! the iPXE script given to clients already running iPXE, and the names too
+//! long for the 'sname' and 'file' fields sent as options 66 and 67 instead.
+
+use dhcp_proto::options::Option;
+use dhcp_proto::{Message, MessageType, MAGIC_COOKIE};
+use rdhcp::boot;
+use rdhcp::configuration::Boot;
+use std::net::Ipv4Addr;
+
+const NEXT_SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 5);
+
+/// DHCPDISCOVER carrying `options`
+fn discover(options: &[(Option, &[u8])]) -> Message {
+    let mut packet = vec![0u8; 236];
+    packet[..3].copy_from_slice(&[1, 1, 6]);
+    packet[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    packet.extend_from_slice(&[Option::DHCPMessageType as u8, 1, 1]);
+    for (op, data) in options {
+        packet.extend_from_slice(&[*op as u8, data.len() as u8]);
+        packet.extend_from_slice(data);
+    }
+    packet.push(255);
+    Message::deserialize(&packet).expect("valid DHCPDISCOVER")
+}
+
+fn offer(boot: &Boot, request: &Message) -> Message {
+    let reply = Message::reply_to(request, MessageType::DHCPOFFER);
+    let reply = boot::apply(boot, None, request, reply);
+    assert!(reply.option(Option::OptionOverload).is_none());
+    reply
+}
+
+/// Content of the 'sname' or 'file' field
+fn field(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn option(reply: &Message, op: Option) -> std::option::Option<String> {
+    reply
+        .option(op)
+        .map(|op| String::from_utf8_lossy(&op.data).to_string())
+}
+
+#[test]
+fn file_by_architecture() {
+    let boot = Boot {
+        next_server: Some(NEXT_SERVER),
+        filename: Some("default.efi".to_string()),
+        bios: Some("pxelinux.0".to_string()),
+        uefi_x64: Some("x64/grubx64.efi".to_string()),
+        uefi_arm64: Some("arm64/grubaa64.efi".to_string()),
+        ..Boot::default()
+    };
+    for (architecture, expected) in [
+        (Some([0, 0]), "pxelinux.0"),
+        (Some([0, 7]), "x64/grubx64.efi"),
+        (Some([0, 11]), "arm64/grubaa64.efi"),
+        // UEFI IA32 has no file of its own
+        (Some([0, 6]), "default.efi"),
+        (None, "default.efi"),
+    ] {
+        let request = match architecture {
+            Some(code) => discover(&[(Option::ClientSystemArchitecture, &code)]),
+            None => discover(&[]),
+        };
+        let reply = offer(&boot, &request);
+        assert_eq!(
+            field(&reply.file),
+            expected,
+            "architecture {:?}",
+            architecture
+        );
+        assert_eq!(reply.siaddr, NEXT_SERVER);
+        assert_eq!(option(&reply, Option::BootfileName), None);
+    }
+}
+
+#[test]
+fn long_names_are_sent_as_options() {
+    // The longest names the fields hold, with their null terminator
+    let fitting = Boot {
+        server_name: Some("s".repeat(63)),
+        filename: Some("f".repeat(127)),
+        ..Boot::default()
+    };
+    let reply = offer(&fitting, &discover(&[]));
+    assert_eq!(field(&reply.sname), "s".repeat(63));
+    assert_eq!(field(&reply.file), "f".repeat(127));
+    assert_eq!(option(&reply, Option::TFTPServerName), None);
+    assert_eq!(option(&reply, Option::BootfileName), None);
+
+    let long = Boot {
+        server_name: Some("s".repeat(64)),
+        filename: Some(format!("http://boot.example.com/{}", "f".repeat(104))),
+        ..Boot::default()
+    };
+    let reply = offer(&long, &discover(&[]));
+    assert_eq!(field(&reply.sname), "");
+    assert_eq!(field(&reply.file), "");
+    assert_eq!(option(&reply, Option::TFTPServerName), long.server_name);
+    assert_eq!(option(&reply, Option::BootfileName), long.filename);
+}
+
+#[test]
+fn requested_options_are_sent_too() {
+    let boot = Boot {
+        server_name: Some("tftp.example.com".to_string()),
+        filename: Some("pxelinux.0".to_string()),
+        ..Boot::default()
+    };
+    let requested = [Option::TFTPServerName as u8, Option::BootfileName as u8];
+    let reply = offer(
+        &boot,
+        &discover(&[(Option::ParameterRequestList, &requested)]),
+    );
+    assert_eq!(field(&reply.sname), "tftp.example.com");
+    assert_eq!(field(&reply.file), "pxelinux.0");
+    assert_eq!(option(&reply, Option::TFTPServerName), boot.server_name);
+    assert_eq!(option(&reply, Option::BootfileName), boot.filename);
+}
+
+#[test]
+fn ipxe_gets_the_script() {
+    let boot = Boot {
+        uefi_x64: Some("ipxe.efi".to_string()),
+        ipxe_script: Some("http://boot.example.com/boot.ipxe".to_string()),
+        ..Boot::default()
+    };
+    let x64: &[u8] = &[0, 7];
+    // The firmware ROM chainloads iPXE, which then asks again
+    let rom = discover(&[(Option::ClientSystemArchitecture, x64)]);
+    assert_eq!(field(&offer(&boot, &rom).file), "ipxe.efi");
+    // Sent as is by iPXE, or as a list of classes as RFC 3004 describes
+    for user_class in [&b"iPXE"[..], &b"\x04iPXE"[..]] {
+        let ipxe = discover(&[
+            (Option::ClientSystemArchitecture, x64),
+            (Option::UserClass, user_class),
+        ]);
+        let reply = offer(&boot, &ipxe);
+        assert_eq!(field(&reply.file), "http://boot.example.com/boot.ipxe");
+    }
+    // Another user class is a firmware ROM
+    let other = discover(&[
+        (Option::ClientSystemArchitecture, x64),
+        (Option::UserClass, b"gPXE"),
+    ]);
+    assert_eq!(field(&offer(&boot, &other).file), "ipxe.efi");
+}
@@ -0,0 +1,104 @@
+//! `rdhcp --check` on configuration files with one kind of problem each, the
+//! problems are reported with the position of the faulty value.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const SUBNET: &str = r#"
+[[subnet]]
+network = "192.168.1.0"
+subnet_mask = "255.255.255.0"
+
+[[subnet.range]]
+start_address = "192.168.1.10"
+end_address = "192.168.1.20"
+"#;
+
+/// Problems reported for a configuration, `name` tells the files apart
+fn check(name: &str, content: &str) -> Vec<String> {
+    let path: PathBuf =
+        std::env::temp_dir().join(format!("rdhcp-check-{}-{}.toml", name, std::process::id()));
+    fs::write(&path, content).expect("temporary configuration");
+    let output = Command::new(env!("CARGO_BIN_EXE_rdhcp"))
+        .arg("--conf")
+        .arg(&path)
+        .arg("--check")
+        .output()
+        .expect("rdhcp");
+    fs::remove_file(&path).expect("temporary configuration removed");
+    let prefix = format!("{}:", path.display());
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.ends_with(" is valid"))
+        .map(|line| line.replacen(&prefix, "", 1))
+        .collect()
+}
+
+#[test]
+fn valid() {
+    assert_eq!(check("valid", SUBNET), Vec::<String>::new());
+}
+
+#[test]
+fn lease_times() {
+    let content = format!(
+        "{}{}",
+        SUBNET,
+        r#"
+[subnet.lease]
+default = 60
+min = 300
+renewal = 0.9
+rebinding = 0.5
+
+[[subnet.host]]
+hardware_address = "02:00:00:00:00:01"
+lease = { renewal = nan, rebinding = 1.5 }
+"#
+    );
+    assert_eq!(
+        check("lease", &content),
+        [
+            "14:13: subnet 192.168.1.0 : rebinding 0.5 should be greater than renewal 0.9",
+            "11:11: subnet 192.168.1.0 : default lease 60 should be between min 300 and max 86400",
+            "18:21: host 02:00:00:00:00:01 : renewal NaN should be between 0 and 1",
+            "18:38: host 02:00:00:00:00:01 : rebinding 1.5 should be between 0 and 1",
+        ]
+    );
+}
+
+#[test]
+fn failover_secret() {
+    let content = format!(
+        "{}{}",
+        r#"
+[failover]
+role = "primary"
+address = "127.0.0.1:8647"
+secret = ""
+"#,
+        SUBNET
+    );
+    assert_eq!(check("failover", &content), ["5:10: failover secret is empty"]);
+}
+
+#[test]
+fn api_token() {
+    let content = format!("{}{}", "\n[api]\ntoken = \"\"\n", SUBNET);
+    assert_eq!(check("api", &content), ["3:9: API token is empty"]);
+}
+
+#[test]
+fn server_identifier() {
+    let content = format!("{}{}", "listen = \"0.0.0.0:67\"\n", SUBNET);
+    assert_eq!(
+        check("listen", &content),
+        ["1:10: server_identifier is required when listening on 0.0.0.0:67"]
+    );
+    let content = format!("{}{}", "server_identifier = \"0.0.0.0\"\n", SUBNET);
+    assert_eq!(
+        check("identifier", &content),
+        ["1:21: server identifier 0.0.0.0 can't be reached by the clients"]
+    );
+}
@@ -0,0 +1,215 @@
+//! Client classes : the matching on options 60, 77 and 82 and on the
+//! hardware address, the ranges allowed, denied or preferred by class, and
+//! the class options overriding the ones of the subnet.
+
+use dhcp_proto::options::Option;
+use dhcp_proto::{Message, MAGIC_COOKIE};
+use rdhcp::configuration::Configuration;
+use rdhcp::probe::NoProber;
+use rdhcp::server::{ErrorPool, Pool};
+use std::net::Ipv4Addr;
+
+const CONFIGURATION: &str = r#"
+[[subnet]]
+network = "192.0.2.0"
+subnet_mask = "255.255.255.0"
+
+[subnet.options]
+domain-name = "example.com"
+interface-mtu = 1500
+
+[[subnet.range]]
+start_address = "192.0.2.10"
+end_address = "192.0.2.19"
+deny = ["ipxe"]
+
+[[subnet.range]]
+start_address = "192.0.2.20"
+end_address = "192.0.2.21"
+prefer = ["voip"]
+
+[[subnet.range]]
+start_address = "192.0.2.30"
+end_address = "192.0.2.30"
+allow = ["pxe"]
+
+[[class]]
+name = "pxe"
+match = { vendor_class = "PXEClient" }
+
+[[class]]
+name = "ipxe"
+match = { user_class = "iPXE" }
+
+[[class]]
+name = "voip"
+match = { oui = "00:04:F2" }
+
+[class.options]
+domain-name = "voip.example.com"
+
+[[class]]
+name = "port"
+match = { circuit_id = "eth0/1", remote_id = "switch-1" }
+"#;
+
+fn configuration() -> Configuration {
+    toml::from_str(CONFIGURATION).expect("valid configuration")
+}
+
+/// DHCPDISCOVER of `chaddr` carrying `options`
+fn discover(chaddr: [u8; 6], options: &[(Option, &[u8])]) -> Message {
+    let mut packet = vec![0u8; 236];
+    packet[..3].copy_from_slice(&[1, 1, 6]);
+    packet[28..34].copy_from_slice(&chaddr);
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    packet.extend_from_slice(&[Option::DHCPMessageType as u8, 1, 1]);
+    for (op, data) in options {
+        packet.extend_from_slice(&[*op as u8, data.len() as u8]);
+        packet.extend_from_slice(data);
+    }
+    packet.push(255);
+    Message::deserialize(&packet).expect("valid DHCPDISCOVER")
+}
+
+/// Relay agent information with a circuit ID and a remote ID
+fn relay_agent(circuit_id: &[u8], remote_id: &[u8]) -> Vec<u8> {
+    let mut data = vec![1, circuit_id.len() as u8];
+    data.extend_from_slice(circuit_id);
+    data.extend_from_slice(&[2, remote_id.len() as u8]);
+    data.extend_from_slice(remote_id);
+    data
+}
+
+/// The first range of the subnet
+const RANGE: std::ops::RangeInclusive<Ipv4Addr> =
+    Ipv4Addr::new(192, 0, 2, 10)..=Ipv4Addr::new(192, 0, 2, 19);
+
+fn mac(index: u8) -> String {
+    format!("02:00:00:00:00:{:02x}", index)
+}
+
+#[test]
+fn matching() {
+    let configuration = configuration();
+    let other: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    let cases: [(Message, &[&str]); 8] = [
+        (discover(other, &[]), &[]),
+        (
+            discover(
+                other,
+                &[(Option::ClassIdentifier, b"PXEClient:Arch:00007:UNDI:003016")],
+            ),
+            &["pxe"],
+        ),
+        // A prefix of the vendor class, not a part of it
+        (
+            discover(other, &[(Option::ClassIdentifier, b"AAPXEClient")]),
+            &[],
+        ),
+        (discover(other, &[(Option::UserClass, b"iPXE")]), &["ipxe"]),
+        (discover([0x00, 0x04, 0xf2, 1, 2, 3], &[]), &["voip"]),
+        (
+            discover(
+                other,
+                &[(
+                    Option::RelayAgentInformation,
+                    &relay_agent(b"eth0/1", b"switch-1"),
+                )],
+            ),
+            &["port"],
+        ),
+        // Both sub-options must match
+        (
+            discover(
+                other,
+                &[(
+                    Option::RelayAgentInformation,
+                    &relay_agent(b"eth0/1", b"switch-2"),
+                )],
+            ),
+            &[],
+        ),
+        (
+            discover(
+                [0x00, 0x04, 0xf2, 1, 2, 3],
+                &[(Option::ClassIdentifier, b"PXEClient")],
+            ),
+            &["pxe", "voip"],
+        ),
+    ];
+    for (msg, expected) in cases.iter() {
+        assert_eq!(configuration.classify(msg), *expected, "{}", msg);
+    }
+}
+
+#[test]
+fn restricted_range_refuses_other_clients() {
+    let mut configuration = configuration();
+    // Only the range restricted to the pxe class is left
+    configuration.subnets[0].ranges.drain(..2);
+    let mut pool = Pool::with_prober(configuration, Box::new(NoProber));
+
+    let refused = pool.reserve_ip(mac(1), 0, &["ipxe".to_string()]);
+    assert!(matches!(refused, Err(ErrorPool::PoolExhausted)));
+    let client = pool
+        .reserve_ip(mac(2), 0, &["pxe".to_string()])
+        .expect("address of the pxe range");
+    assert_eq!(client.address(), Ipv4Addr::new(192, 0, 2, 30));
+}
+
+#[test]
+fn denied_range_is_skipped() {
+    let mut configuration = configuration();
+    // The range denied to the ipxe class, and the one restricted to pxe
+    configuration.subnets[0].ranges.remove(1);
+    let mut pool = Pool::with_prober(configuration, Box::new(NoProber));
+
+    let refused = pool.reserve_ip(mac(1), 0, &["ipxe".to_string()]);
+    assert!(matches!(refused, Err(ErrorPool::PoolExhausted)));
+    let client = pool.reserve_ip(mac(2), 0, &["voip".to_string()]).unwrap();
+    assert!(RANGE.contains(&client.address()));
+}
+
+#[test]
+fn preferred_range_first() {
+    let mut pool = Pool::with_prober(configuration(), Box::new(NoProber));
+    let voip = ["voip".to_string()];
+    let mut addresses: Vec<Ipv4Addr> = (1..=2)
+        .map(|index| pool.reserve_ip(mac(index), 0, &voip).unwrap().address())
+        .collect();
+    addresses.sort();
+    assert_eq!(
+        addresses,
+        [Ipv4Addr::new(192, 0, 2, 20), Ipv4Addr::new(192, 0, 2, 21)]
+    );
+    // Then the other ranges, once the preferred one is full
+    let address = pool.reserve_ip(mac(3), 0, &voip).unwrap().address();
+    assert!(RANGE.contains(&address));
+}
+
+#[test]
+fn class_options_override_the_subnet_ones() {
+    let configuration = configuration();
+    let value = |classes: &[String], op: Option| {
+        let options = configuration.options_for(0, classes).unwrap();
+        options
+            .into_iter()
+            .find(|option| option.op_code == op as u8)
+            .map(|option| option.data)
+    };
+    assert_eq!(
+        value(&[], Option::DomainName),
+        Some(b"example.com".to_vec())
+    );
+    let voip = ["voip".to_string()];
+    assert_eq!(
+        value(&voip, Option::DomainName),
+        Some(b"voip.example.com".to_vec())
+    );
+    // Options the class doesn't set are the ones of the subnet
+    assert_eq!(
+        value(&voip, Option::InterfaceMTU),
+        Some(1500u16.to_be_bytes().to_vec())
+    );
+}
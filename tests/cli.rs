@@ -0,0 +1,50 @@
+//! Command line : the configuration file is only required when something has
+//! to be read from it.
+
+use std::process::{Command, Output};
+
+fn rdhcp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rdhcp"))
+        .args(args)
+        .output()
+        .expect("rdhcp")
+}
+
+fn error(output: &Output) -> String {
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).trim().to_string()
+}
+
+#[test]
+fn configuration_required() {
+    assert_eq!(
+        error(&rdhcp(&["--check"])),
+        "ERR: --conf is required to validate the configuration"
+    );
+    assert_eq!(
+        error(&rdhcp(&["leases", "--api", "127.0.0.1:1"])),
+        "ERR: --conf is required unless --api and --token are given"
+    );
+    assert_eq!(
+        error(&rdhcp(&[])),
+        "ERR: --conf is required to run the server"
+    );
+}
+
+#[test]
+fn api_commands_without_configuration() {
+    // Nothing listens on port 1, the request is sent without reading a file
+    for command in [
+        &["leases"][..],
+        &["history", "02:00:00:00:00:01"],
+        &["release", "192.0.2.10"],
+    ] {
+        let args = [command, &["--api", "127.0.0.1:1", "--token", "secret"]].concat();
+        let error = error(&rdhcp(&args));
+        assert!(
+            error.starts_with("ERR: unable to reach the server on 127.0.0.1:1"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,505 @@
+//! DNS updates sent to a stub DNS server : the encoding of the updates, the
+//! TSIG signature of the requests and of the responses, their order, and
+//! the DHCID prerequisites protecting the names of the other clients.
+//!
+//! The stub computes the TSIG MACs on its own, following RFC 8945, and
+//! checks the prerequisites against the records it holds.
+
+use base64::Engine;
+use hmac::{Mac, SimpleHmac};
+use rdhcp::configuration::{Ddns, TsigKey};
+use rdhcp::ddns::{self, Change, DnsUpdater};
+use rdhcp::fqdn::Updates;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+const MAC: &str = "02:00:00:00:00:01";
+const OTHER_MAC: &str = "02:00:00:00:00:02";
+const BOTH: Updates = Updates {
+    forward: true,
+    reverse: true,
+};
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_PTR: u16 = 12;
+const TYPE_DHCID: u16 = 49;
+const TYPE_TSIG: u16 = 250;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const NOERROR: u8 = 0;
+const YXDOMAIN: u8 = 6;
+const NXRRSET: u8 = 8;
+
+/// How the stub signs its responses
+#[derive(Clone, Copy)]
+enum Signature {
+    None,
+    Valid,
+    Forged,
+    /// TSIG error BADKEY, without MAC
+    BadKey,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Record {
+    name: String,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+/// Update received by the stub
+#[derive(Debug, PartialEq)]
+struct Update {
+    zone: String,
+    prerequisites: Vec<Record>,
+    records: Vec<Record>,
+    /// Whether the TSIG record of the request is valid, None if unsigned
+    signed: Option<bool>,
+    /// Answer of the stub
+    rcode: u8,
+}
+
+struct Reader<'a> {
+    message: &'a [u8],
+    offset: usize,
+}
+impl Reader<'_> {
+    fn u16(&mut self) -> u16 {
+        let value = u16::from_be_bytes([self.message[self.offset], self.message[self.offset + 1]]);
+        self.offset += 2;
+        value
+    }
+    fn u32(&mut self) -> u32 {
+        (u32::from(self.u16()) << 16) | u32::from(self.u16())
+    }
+    fn bytes(&mut self, len: usize) -> &[u8] {
+        self.offset += len;
+        &self.message[self.offset - len..self.offset]
+    }
+    /// Names are never compressed in the updates
+    fn name(&mut self) -> String {
+        let mut labels = Vec::new();
+        loop {
+            let len = usize::from(self.bytes(1)[0]);
+            if len == 0 {
+                return labels.join(".");
+            }
+            labels.push(String::from_utf8_lossy(self.bytes(len)).to_string());
+        }
+    }
+    fn record(&mut self) -> Record {
+        let name = self.name();
+        let (rtype, class, ttl) = (self.u16(), self.u16(), self.u32());
+        let len = usize::from(self.u16());
+        Record {
+            name,
+            rtype,
+            class,
+            ttl,
+            rdata: self.bytes(len).to_vec(),
+        }
+    }
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.push(0);
+    bytes
+}
+
+fn mac(data: &[u8]) -> Vec<u8> {
+    let mut mac = SimpleHmac::<sha2::Sha256>::new_from_slice(SECRET).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// TSIG variables, RFC 8945 section 4.3.3
+fn variables(time_signed: &[u8], fudge: u16, error: u16) -> Vec<u8> {
+    let mut variables = encode_name("rdhcp-key");
+    variables.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    variables.extend_from_slice(&0u32.to_be_bytes());
+    variables.extend(encode_name("hmac-sha256"));
+    variables.extend_from_slice(time_signed);
+    variables.extend_from_slice(&fudge.to_be_bytes());
+    variables.extend_from_slice(&error.to_be_bytes());
+    variables.extend_from_slice(&0u16.to_be_bytes());
+    variables
+}
+
+/// Read an update, the MAC of its TSIG record is returned with it
+fn parse(message: &[u8]) -> (Update, Vec<u8>) {
+    let mut reader = Reader { message, offset: 4 };
+    let counts = [reader.u16(), reader.u16(), reader.u16(), reader.u16()];
+    assert_eq!(
+        u16::from_be_bytes([message[2], message[3]]) >> 11,
+        5,
+        "opcode UPDATE"
+    );
+    assert_eq!(counts[0], 1, "one zone");
+    let zone = reader.name();
+    assert_eq!((reader.u16(), reader.u16()), (TYPE_SOA, CLASS_IN));
+    let prerequisites: Vec<Record> = (0..counts[1]).map(|_| reader.record()).collect();
+    let records: Vec<Record> = (0..counts[2]).map(|_| reader.record()).collect();
+    if counts[3] == 0 {
+        let update = Update {
+            zone,
+            prerequisites,
+            records,
+            signed: None,
+            rcode: NOERROR,
+        };
+        return (update, Vec::new());
+    }
+    let tsig_start = reader.offset;
+    let tsig = reader.record();
+    assert_eq!((tsig.name.as_str(), tsig.rtype), ("rdhcp-key", TYPE_TSIG));
+    let mut rdata = Reader {
+        message: &tsig.rdata,
+        offset: 0,
+    };
+    assert_eq!(rdata.name(), "hmac-sha256");
+    let time_signed = rdata.bytes(6).to_vec();
+    let fudge = rdata.u16();
+    let len = usize::from(rdata.u16());
+    let request_mac = rdata.bytes(len).to_vec();
+
+    let mut signed = message[..tsig_start].to_vec();
+    signed[11] -= 1; // ARCOUNT without the TSIG record
+    signed.extend(variables(&time_signed, fudge, 0));
+    let update = Update {
+        zone,
+        prerequisites,
+        records,
+        signed: Some(mac(&signed) == request_mac),
+        rcode: NOERROR,
+    };
+    (update, request_mac)
+}
+
+/// Check the prerequisites of an update against the records of the zone,
+/// then apply it, RFC 2136 sections 3.2 and 3.4
+fn evaluate(zone: &mut Vec<Record>, update: &Update) -> u8 {
+    let same = |a: &Record, b: &Record| a.name == b.name && a.rtype == b.rtype;
+    for prerequisite in update.prerequisites.iter() {
+        if (prerequisite.class, prerequisite.rtype) == (CLASS_NONE, TYPE_ANY) {
+            if zone.iter().any(|record| record.name == prerequisite.name) {
+                return YXDOMAIN;
+            }
+        } else if !zone
+            .iter()
+            .any(|record| same(record, prerequisite) && record.rdata == prerequisite.rdata)
+        {
+            return NXRRSET;
+        }
+    }
+    for change in update.records.iter() {
+        match change.class {
+            CLASS_ANY => zone.retain(|record| !same(record, change)),
+            CLASS_NONE => {
+                zone.retain(|record| !(same(record, change) && record.rdata == change.rdata))
+            }
+            _ => zone.push(change.clone()),
+        }
+    }
+    NOERROR
+}
+
+/// Response to an update, signed as asked
+fn response(request: &[u8], request_mac: &[u8], signature: Signature, rcode: u8) -> Vec<u8> {
+    // Header and zone section of the request, QR set
+    let zone_end = 12 + request[12..].iter().position(|&byte| byte == 0).unwrap() + 1 + 4;
+    let mut response = request[..zone_end].to_vec();
+    response[2] |= 0x80;
+    response[3] = (response[3] & 0xf0) | rcode;
+    response[6..12].copy_from_slice(&[0, 0, 0, 0, 0, 0]);
+    let (error, mac_of) = match signature {
+        Signature::None => return response,
+        Signature::BadKey => (17, None),
+        Signature::Valid | Signature::Forged => (0, Some(request_mac)),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let time_signed = &now.to_be_bytes()[2..];
+    let mut mac = match mac_of {
+        Some(request_mac) => {
+            let mut signed = (request_mac.len() as u16).to_be_bytes().to_vec();
+            signed.extend_from_slice(request_mac);
+            signed.extend_from_slice(&response);
+            signed.extend(variables(time_signed, 300, error));
+            mac(&signed)
+        }
+        None => Vec::new(),
+    };
+    if let (Signature::Forged, Some(byte)) = (signature, mac.first_mut()) {
+        *byte ^= 0xff;
+    }
+    let mut rdata = encode_name("hmac-sha256");
+    rdata.extend_from_slice(time_signed);
+    rdata.extend_from_slice(&300u16.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend(mac);
+    rdata.extend_from_slice(&request[..2]); // original id
+    rdata.extend_from_slice(&error.to_be_bytes());
+    rdata.extend_from_slice(&0u16.to_be_bytes());
+    response.extend(encode_name("rdhcp-key"));
+    response.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+    response.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    response.extend_from_slice(&0u32.to_be_bytes());
+    response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    response.extend(rdata);
+    response[11] = 1;
+    response
+}
+
+/// Answer every update made to a zone holding `records`, the ones received
+/// are sent back to the test
+fn stub(signature: Signature, records: Vec<Record>) -> (SocketAddr, Receiver<Update>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let (updates, received) = mpsc::channel();
+    thread::spawn(move || {
+        let mut zone = records;
+        let mut buffer = [0u8; 512];
+        loop {
+            let (len, client) = socket.recv_from(&mut buffer).unwrap();
+            let (mut update, request_mac) = parse(&buffer[..len]);
+            update.rcode = evaluate(&mut zone, &update);
+            let answer = response(&buffer[..len], &request_mac, signature, update.rcode);
+            socket.send_to(&answer, client).unwrap();
+            if updates.send(update).is_err() {
+                return;
+            }
+        }
+    });
+    (address, received)
+}
+
+fn updater(server: SocketAddr, signed: bool) -> DnsUpdater {
+    DnsUpdater::new(Ddns {
+        server,
+        zone: "example.com.".to_string(),
+        reverse_zone: None,
+        ttl: 300,
+        client_updates: false,
+        tsig: signed.then(|| TsigKey {
+            name: "rdhcp-key".to_string(),
+            algorithm: "hmac-sha256".to_string(),
+            secret: base64::engine::general_purpose::STANDARD.encode(SECRET),
+        }),
+    })
+}
+
+fn record(name: &str, rtype: u16, class: u16, ttl: u32, rdata: &[u8]) -> Record {
+    Record {
+        name: name.to_string(),
+        rtype,
+        class,
+        ttl,
+        rdata: rdata.to_vec(),
+    }
+}
+
+/// A and DHCID records of `mac` for host.example.com
+fn owned_by(mac: &str, address: Ipv4Addr) -> Vec<Record> {
+    vec![
+        record("host.example.com", TYPE_A, CLASS_IN, 300, &address.octets()),
+        record(
+            "host.example.com",
+            TYPE_DHCID,
+            CLASS_IN,
+            300,
+            &ddns::dhcid(mac, "host.example.com."),
+        ),
+    ]
+}
+
+#[test]
+fn dhcid() {
+    // RFC 4701 section 3.6, second example
+    let expected = base64::engine::general_purpose::STANDARD
+        .decode("AAABxLmlskllE0MVjd57zHcWmEH3pCQ6VytcKD//7es/deY=")
+        .unwrap();
+    assert_eq!(
+        ddns::dhcid("01:02:03:04:05:06", "client.example.com."),
+        expected
+    );
+}
+
+#[test]
+fn signed_register() {
+    let (server, updates) = stub(Signature::Valid, Vec::new());
+    let change = Change::Register(MAC.to_string(), "host".to_string(), ADDRESS, BOTH);
+    assert_eq!(updater(server, true).apply(&change), Ok(()));
+
+    let forward = updates.recv().unwrap();
+    assert_eq!(
+        forward,
+        Update {
+            zone: "example.com".to_string(),
+            prerequisites: vec![record("host.example.com", TYPE_ANY, CLASS_NONE, 0, &[])],
+            records: owned_by(MAC, ADDRESS),
+            signed: Some(true),
+            rcode: NOERROR,
+        }
+    );
+    let reverse = updates.recv().unwrap();
+    assert_eq!(
+        reverse,
+        Update {
+            zone: "2.0.192.in-addr.arpa".to_string(),
+            prerequisites: Vec::new(),
+            records: vec![
+                record("10.2.0.192.in-addr.arpa", TYPE_PTR, CLASS_ANY, 0, &[]),
+                record(
+                    "10.2.0.192.in-addr.arpa",
+                    TYPE_PTR,
+                    CLASS_IN,
+                    300,
+                    &encode_name("host.example.com")
+                ),
+            ],
+            signed: Some(true),
+            rcode: NOERROR,
+        }
+    );
+}
+
+#[test]
+fn own_name_is_updated() {
+    let previous = Ipv4Addr::new(192, 0, 2, 20);
+    let (server, updates) = stub(Signature::None, owned_by(MAC, previous));
+    let forward_only = Updates {
+        forward: true,
+        reverse: false,
+    };
+    let change = Change::Register(MAC.to_string(), "host".to_string(), ADDRESS, forward_only);
+    assert_eq!(updater(server, false).apply(&change), Ok(()));
+
+    assert_eq!(updates.recv().unwrap().rcode, YXDOMAIN);
+    let replaced = updates.recv().unwrap();
+    let dhcid = ddns::dhcid(MAC, "host.example.com.");
+    assert_eq!(
+        replaced.prerequisites,
+        [record("host.example.com", TYPE_DHCID, CLASS_IN, 0, &dhcid)]
+    );
+    assert_eq!(
+        replaced.records,
+        [
+            record("host.example.com", TYPE_A, CLASS_ANY, 0, &[]),
+            record("host.example.com", TYPE_A, CLASS_IN, 300, &ADDRESS.octets()),
+        ]
+    );
+    assert_eq!(replaced.rcode, NOERROR);
+}
+
+#[test]
+fn conflicting_name_is_refused() {
+    let taken = Ipv4Addr::new(192, 0, 2, 20);
+    let by_hand = vec![record(
+        "host.example.com",
+        TYPE_A,
+        CLASS_IN,
+        300,
+        &taken.octets(),
+    )];
+    for records in [owned_by(OTHER_MAC, taken), by_hand] {
+        let (server, updates) = stub(Signature::None, records);
+        let change = Change::Register(MAC.to_string(), "host".to_string(), ADDRESS, BOTH);
+        assert_eq!(
+            updater(server, false).apply(&change),
+            Err("host.example.com. : name in use by another client".to_string())
+        );
+        let rcodes: Vec<u8> = updates.iter().take(2).map(|update| update.rcode).collect();
+        assert_eq!(rcodes, [YXDOMAIN, NXRRSET]);
+        // Nothing else is sent, the PTR record included
+        assert!(updates.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
+
+#[test]
+fn removal_keeps_the_records_of_other_clients() {
+    let (server, updates) = stub(Signature::None, owned_by(OTHER_MAC, ADDRESS));
+    let forward_only = Updates {
+        forward: true,
+        reverse: false,
+    };
+    let change = Change::Remove(MAC.to_string(), "host".to_string(), ADDRESS, forward_only);
+    assert_eq!(
+        updater(server, false).apply(&change),
+        Err("host.example.com. : name in use by another client, left in place".to_string())
+    );
+    assert_eq!(updates.recv().unwrap().rcode, NXRRSET);
+}
+
+#[test]
+fn responses_are_verified() {
+    let change = Change::Remove(
+        MAC.to_string(),
+        "host".to_string(),
+        ADDRESS,
+        Updates {
+            forward: true,
+            reverse: false,
+        },
+    );
+    for (signature, error) in [
+        (Signature::Forged, "invalid signature of the response"),
+        (Signature::None, "unsigned response"),
+        (Signature::BadKey, "server refused the signature : BADKEY"),
+    ] {
+        let (server, updates) = stub(signature, Vec::new());
+        assert_eq!(
+            updater(server, true).apply(&change),
+            Err(format!("host.example.com. : {}", error))
+        );
+        assert_eq!(updates.recv().unwrap().signed, Some(true));
+    }
+}
+
+#[test]
+fn changes_are_sent_in_order() {
+    let old = Change::Register(MAC.to_string(), "old".to_string(), ADDRESS, BOTH);
+    let (server, updates) = stub(Signature::None, Vec::new());
+    let updater = updater(server, false);
+    assert_eq!(updater.apply(&old), Ok(()));
+    assert_eq!(updates.iter().take(2).count(), 2);
+    // A hostname change
+    updater.remove(MAC, "old", ADDRESS, BOTH);
+    updater.register(MAC, "new", ADDRESS, BOTH);
+    let received: Vec<Update> = (0..4)
+        .map(|_| updates.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    let first: Vec<(&str, u16, u16)> = received
+        .iter()
+        .map(|update| {
+            let record = &update.records[0];
+            (record.name.as_str(), record.rtype, record.class)
+        })
+        .collect();
+    assert_eq!(
+        first,
+        [
+            ("old.example.com", TYPE_A, CLASS_NONE),
+            ("10.2.0.192.in-addr.arpa", TYPE_PTR, CLASS_ANY),
+            ("new.example.com", TYPE_A, CLASS_IN),
+            ("10.2.0.192.in-addr.arpa", TYPE_PTR, CLASS_ANY),
+        ]
+    );
+    assert_eq!(received[3].records.len(), 2);
+    assert!(received.iter().all(|update| update.signed.is_none()));
+    assert!(received.iter().all(|update| update.rcode == NOERROR));
+}
@@ -0,0 +1,229 @@
+//! Failover : the states of a server and the split of the addresses, the
+//! authentication of the peers, the updates queued while the peer is
+//! unreachable, and the bindings of both peers conflicting.
+//!
+//! The fake peers compute the HMAC of the handshake on their own.
+
+use hmac::{Mac, SimpleHmac};
+use rdhcp::configuration::{Configuration, Failover};
+use rdhcp::failover::{self, Event, FailoverState, Role, State, Update};
+use rdhcp::probe::NoProber;
+use rdhcp::server::Pool;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECRET: &str = "shared secret";
+const EVEN: Ipv4Addr = Ipv4Addr::new(192, 0, 0, 10);
+const ODD: Ipv4Addr = Ipv4Addr::new(192, 0, 0, 11);
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Challenge sent by the fake peers
+const CHALLENGE: &str = "00112233445566778899aabbccddeeff";
+
+fn configuration(role: Role, address: SocketAddr, secret: &str) -> Failover {
+    Failover {
+        role,
+        address,
+        secret: secret.to_string(),
+        heartbeat_timeout: 5,
+        mclt: 0,
+    }
+}
+
+/// Loopback address nobody listens on
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port")
+}
+
+/// Start a peer, returns the queue of its updates and its events
+fn peer(configuration: Failover) -> (Sender<Update>, Receiver<Event>) {
+    let (updates_tx, updates_rx) = mpsc::channel();
+    let (events_tx, events_rx) = mpsc::channel();
+    failover::start(configuration, updates_rx, events_tx).expect("failover started");
+    (updates_tx, events_rx)
+}
+
+fn proof(secret: &str, role: &str, challenge: &str) -> String {
+    let mut mac = SimpleHmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{} {}", role, challenge).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).expect("line from the primary");
+    line.trim_end().to_string()
+}
+
+/// Connect to the primary as a secondary knowing `secret`, returns the
+/// connection and whether the primary proved it knows `secret` too
+fn fake_secondary(address: SocketAddr, secret: &str) -> (BufReader<TcpStream>, bool) {
+    let mut stream = TcpStream::connect(address).expect("primary reachable");
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let line = read_line(&mut reader);
+    let challenge = line
+        .strip_prefix("HELLO ")
+        .expect("challenge of the primary");
+    let answer = proof(secret, "secondary", challenge);
+    stream
+        .write_all(format!("HELLO {}\nAUTH {}\n", CHALLENGE, answer).as_bytes())
+        .unwrap();
+    let primary_answer = read_line(&mut reader);
+    let known = primary_answer == format!("AUTH {}", proof(secret, "primary", CHALLENGE));
+    (reader, known)
+}
+
+#[test]
+fn states() {
+    let address = free_address();
+    let mut primary = FailoverState::new(&configuration(Role::Primary, address, SECRET));
+    let mut secondary = FailoverState::new(&configuration(Role::Secondary, address, SECRET));
+    assert_eq!(primary.state, State::CommunicationsInterrupted);
+
+    for state in [&mut primary, &mut secondary] {
+        state.on_event(&Event::Connected);
+        assert_eq!(state.state, State::Normal);
+    }
+    assert!(primary.owns(EVEN) && !primary.owns(ODD));
+    assert!(secondary.owns(ODD) && !secondary.owns(EVEN));
+
+    // Only the operator confirms the peer is down, never while connected
+    assert!(primary.partner_down().is_err());
+    primary.on_event(&Event::Disconnected);
+    assert_eq!(primary.state, State::CommunicationsInterrupted);
+    assert!(!primary.owns(ODD));
+    assert_eq!(primary.partner_down(), Ok(()));
+    assert_eq!(primary.state, State::PartnerDown);
+    assert!(primary.owns(EVEN) && primary.owns(ODD));
+
+    primary.on_event(&Event::Connected);
+    assert_eq!(primary.state, State::Normal);
+    assert!(!primary.owns(ODD));
+
+    // The addresses of the peer wait for the MCLT
+    let mut patient = configuration(Role::Secondary, address, SECRET);
+    patient.mclt = 3600;
+    let mut patient = FailoverState::new(&patient);
+    assert_eq!(patient.partner_down(), Ok(()));
+    assert_eq!(patient.state, State::PartnerDown);
+    assert!(patient.owns(ODD) && !patient.owns(EVEN));
+}
+
+#[test]
+fn updates_between_peers() {
+    let address = free_address();
+    let (primary_updates, primary_events) = peer(configuration(Role::Primary, address, SECRET));
+    let (secondary_updates, secondary_events) =
+        peer(configuration(Role::Secondary, address, SECRET));
+    for events in [&primary_events, &secondary_events] {
+        assert!(matches!(events.recv_timeout(TIMEOUT), Ok(Event::Connected)));
+    }
+
+    let bind = Update::Bind(
+        "02:00:00:00:00:01".to_string(),
+        EVEN,
+        1_700_000_000,
+        "host".to_string(),
+    );
+    primary_updates.send(bind.clone()).unwrap();
+    match secondary_events.recv_timeout(TIMEOUT) {
+        Ok(Event::Update(update)) => assert_eq!(update, bind),
+        other => panic!("expected the binding, got {:?}", other),
+    }
+    let release = Update::Release("02:00:00:00:00:02".to_string(), ODD);
+    secondary_updates.send(release.clone()).unwrap();
+    match primary_events.recv_timeout(TIMEOUT) {
+        Ok(Event::Update(update)) => assert_eq!(update, release),
+        other => panic!("expected the release, got {:?}", other),
+    }
+}
+
+#[test]
+fn peer_without_the_secret() {
+    let address = free_address();
+    let (_updates, events) = peer(configuration(Role::Primary, address, SECRET));
+    let (mut reader, known) = fake_secondary(address, "guessed");
+    assert!(!known);
+    // Disconnected without being connected first
+    assert!(matches!(
+        events.recv_timeout(TIMEOUT),
+        Ok(Event::Disconnected)
+    ));
+    assert_eq!(read_line(&mut reader), "");
+}
+
+#[test]
+fn updates_queued_while_disconnected() {
+    let address = free_address();
+    let primary = configuration(Role::Primary, address, SECRET);
+    let mut state = FailoverState::new(&primary);
+    let (updates, events) = peer(primary);
+    let mut next_event = || {
+        let event = events.recv_timeout(TIMEOUT).expect("failover event");
+        state.on_event(&event);
+        state.state
+    };
+
+    let (reader, known) = fake_secondary(address, SECRET);
+    assert!(known);
+    assert_eq!(next_event(), State::Normal);
+    drop(reader);
+    assert_eq!(next_event(), State::CommunicationsInterrupted);
+
+    let release = Update::Release("02:00:00:00:00:01".to_string(), EVEN);
+    updates.send(release).unwrap();
+    let (mut reader, known) = fake_secondary(address, SECRET);
+    assert!(known);
+    assert_eq!(next_event(), State::Normal);
+    let line = loop {
+        match read_line(&mut reader) {
+            ping if ping == "PING" => continue,
+            line => break line,
+        }
+    };
+    assert_eq!(line, "RELEASE 02:00:00:00:00:01 192.0.0.10");
+}
+
+#[test]
+fn conflicting_bindings() {
+    let mut pool = Pool::with_prober(Configuration::default(), Box::new(NoProber));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let bind = |mac: &str, address: Ipv4Addr, lease: u64| {
+        Update::Bind(mac.to_string(), address, now + lease, String::new())
+    };
+    let holders = |pool: &Pool| {
+        let mut holders: Vec<(String, Ipv4Addr)> = pool
+            .reservation
+            .iter()
+            .map(|(mac, client)| (mac.clone(), client.address()))
+            .collect();
+        holders.sort();
+        holders
+    };
+    let first = "02:00:00:00:00:01".to_string();
+    let second = "02:00:00:00:00:02".to_string();
+
+    pool.apply_update(bind(&first, EVEN, 3600));
+    // The address is bound to another client for longer
+    pool.apply_update(bind(&second, EVEN, 600));
+    assert_eq!(holders(&pool), [(first.clone(), EVEN)]);
+    pool.apply_update(bind(&second, EVEN, 7200));
+    assert_eq!(holders(&pool), [(second.clone(), EVEN)]);
+    // The client is bound to another address for longer
+    pool.apply_update(bind(&second, ODD, 60));
+    assert_eq!(holders(&pool), [(second.clone(), EVEN)]);
+    // A renewal of the same binding is never a conflict
+    pool.apply_update(bind(&second, EVEN, 60));
+    assert_eq!(holders(&pool), [(second, EVEN)]);
+}
@@ -0,0 +1,420 @@
+//! Golden packets : what dhcpcd, ISC dhclient, systemd-networkd, Windows,
+//! iPXE and dhcptest send for the RFC 2131 scenarios, with the replies the
+//! server must give byte for byte.
+//!
+//! The client packets are synthetic : they are rebuilt from the options each
+//! client sends, not recorded from the clients. The replies are recorded from
+//! this server, so comparing them only catches changes of behaviour. What
+//! RFC 2131 asks of the server is checked on its own for every packet, see
+//! `Expected`, the replies are never recorded when these checks fail.
+//!
+//! `tests/golden/<client>/<scenario>.txt` lists the packets of the client,
+//! after "> ", each followed by the replies of the server, after "< ". The
+//! packets are dumped like `hexdump`, a "*" standing for lines repeating the
+//! previous one. Every file is played against a new server, configured by
+//! `tests/golden/rdhcp.toml` and fed through an in-memory transport.
+//!
+//! When the replies change on purpose, record them again and review the
+//! diff :
+//!
+//!     GOLDEN_UPDATE=1 cargo test --test golden
+
+use dhcp_proto::options::Option;
+use dhcp_proto::{Message, MessageType, MAGIC_COOKIE};
+use rdhcp::configuration::Configuration;
+use rdhcp::server::DhcpServer;
+use rdhcp::transport::Transport;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use Expected::{Ack, Nak, Nothing, Offer, Parameters};
+
+// The server identifier of the replies
+const SERVER_IDENTIFIER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+const SERVER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(SERVER_IDENTIFIER, 67));
+// Source of the client packets, the server answers there
+const CLIENT: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 68));
+// How long the server has to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+// Transaction of the DHCPINFORM following every client packet
+const MARKER_XID: u32 = 0x6d61726b;
+// Housekeeping interval of the server, as with a socket
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+// The single address of the range
+const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 100);
+// Default lease of the configuration, no client asks for another one
+const LEASE: u32 = 3600;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// Packets given by the test, replies kept for it
+struct MemoryTransport {
+    received: Receiver<Datagram>,
+    sent: Sender<Datagram>,
+}
+impl Transport for MemoryTransport {
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.received.recv_timeout(RECV_TIMEOUT) {
+            Ok((packet, src)) => {
+                buffer[..packet.len()].copy_from_slice(&packet);
+                Ok((packet.len(), src))
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(RecvTimeoutError::Disconnected) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+    fn send_to(&self, buffer: &[u8], dest: SocketAddr) -> io::Result<usize> {
+        self.sent
+            .send((buffer.to_vec(), dest))
+            .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok(buffer.len())
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SERVER)
+    }
+}
+
+/// A client packet and the replies of the server
+struct Exchange {
+    request: Vec<u8>,
+    replies: Vec<Vec<u8>>,
+}
+
+struct Golden {
+    comments: Vec<String>,
+    exchanges: Vec<Exchange>,
+}
+
+impl Golden {
+    fn parse(content: &str) -> Result<Golden, String> {
+        let mut golden = Golden {
+            comments: Vec::new(),
+            exchanges: Vec::new(),
+        };
+        let mut repeat = false;
+        for (number, line) in content.lines().enumerate() {
+            let error = |message: &str| format!("line {} : {}", number + 1, message);
+            if line.starts_with('#') {
+                golden.comments.push(line.to_string());
+                continue;
+            }
+            if line.starts_with('>') {
+                golden.exchanges.push(Exchange {
+                    request: Vec::new(),
+                    replies: Vec::new(),
+                });
+                continue;
+            }
+            let exchange = golden
+                .exchanges
+                .last_mut()
+                .ok_or_else(|| error("no client packet"))?;
+            if line.starts_with('<') {
+                exchange.replies.push(Vec::new());
+                continue;
+            }
+            let packet = exchange.replies.last_mut().unwrap_or(&mut exchange.request);
+            if line == "*" {
+                repeat = true;
+                continue;
+            }
+            let (offset, bytes) = line.split_once("  ").ok_or_else(|| error("no offset"))?;
+            let offset = usize::from_str_radix(offset, 16).map_err(|_| error("invalid offset"))?;
+            if repeat {
+                let previous = packet[packet.len().saturating_sub(16)..].to_vec();
+                while packet.len() < offset {
+                    packet.extend_from_slice(&previous);
+                }
+                repeat = false;
+            }
+            if packet.len() != offset {
+                return Err(error("unexpected offset"));
+            }
+            for byte in bytes.split_whitespace() {
+                packet.push(u8::from_str_radix(byte, 16).map_err(|_| error("invalid byte"))?);
+            }
+        }
+        Ok(golden)
+    }
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for comment in self.comments.iter() {
+            writeln!(text, "{}", comment).unwrap();
+        }
+        for exchange in self.exchanges.iter() {
+            dump(&mut text, '>', &exchange.request);
+            for reply in exchange.replies.iter() {
+                dump(&mut text, '<', reply);
+            }
+        }
+        text
+    }
+}
+
+/// Packet headed by its message type, 16 bytes a line
+fn dump(text: &mut String, direction: char, packet: &[u8]) {
+    let message_type = Message::deserialize(packet)
+        .ok()
+        .and_then(|message| message.message_type())
+        .map_or("invalid message".to_string(), |message_type| {
+            format!("{:?}", message_type)
+        });
+    writeln!(text, "{} {}", direction, message_type).unwrap();
+    let lines: Vec<&[u8]> = packet.chunks(16).collect();
+    for (i, line) in lines.iter().enumerate() {
+        let repeated = i > 0 && lines[i - 1] == *line;
+        // The last line is always written, it gives the length
+        if repeated && i + 1 < lines.len() {
+            if i < 2 || lines[i - 2] != *line {
+                writeln!(text, "*").unwrap();
+            }
+            continue;
+        }
+        let bytes: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        writeln!(text, "{:04x}  {}", i * 16, bytes.join(" ")).unwrap();
+    }
+}
+
+/// DHCPINFORM sent after every client packet. The server handles the packets
+/// in order, its reply comes after all the replies to the client packet.
+fn marker() -> Vec<u8> {
+    let mut packet = vec![0u8; 236];
+    packet[..4].copy_from_slice(&[1, 1, 6, 0]);
+    packet[4..8].copy_from_slice(&MARKER_XID.to_be_bytes());
+    packet[12..16].copy_from_slice(&[192, 168, 1, 250]); // ciaddr
+    packet[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]); // chaddr
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    packet.extend_from_slice(&[
+        Option::DHCPMessageType as u8,
+        1,
+        MessageType::DHCPINFORM as u8,
+        255,
+    ]);
+    packet
+}
+
+/// Play the client packets against a new server, the replies of each one
+/// are returned
+fn play(golden: &Golden) -> Vec<Vec<Vec<u8>>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/rdhcp.toml");
+    let configuration =
+        Configuration::read(path.display().to_string()).expect("golden configuration");
+    let (requests, received) = mpsc::channel();
+    let (sent, replies) = mpsc::channel();
+    let server = DhcpServer::with_transport(Box::new(MemoryTransport { received, sent }));
+    thread::spawn(move || server.on_recv(configuration));
+
+    let marker = marker();
+    let mut all = Vec::new();
+    for exchange in golden.exchanges.iter() {
+        requests
+            .send((exchange.request.clone(), CLIENT))
+            .expect("server running");
+        requests
+            .send((marker.clone(), CLIENT))
+            .expect("server running");
+        let mut received = Vec::new();
+        loop {
+            let (reply, _) = replies
+                .recv_timeout(REPLY_TIMEOUT)
+                .expect("the server stopped answering");
+            if Message::deserialize(&reply).is_ok_and(|reply| reply.xid == MARKER_XID) {
+                break;
+            }
+            received.push(reply);
+        }
+        all.push(received);
+    }
+    // Dropping the packets sender stops the server
+    all
+}
+
+/// Differences between the expected replies and the received ones
+fn compare(golden: &Golden, received: &[Vec<Vec<u8>>]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (i, (exchange, received)) in golden.exchanges.iter().zip(received).enumerate() {
+        if exchange.replies.len() != received.len() {
+            differences.push(format!(
+                "packet {} : {} replies expected, {} received",
+                i + 1,
+                exchange.replies.len(),
+                received.len()
+            ));
+            continue;
+        }
+        for (expected, reply) in exchange.replies.iter().zip(received) {
+            if expected == reply {
+                continue;
+            }
+            let offset = expected
+                .iter()
+                .zip(reply)
+                .position(|(a, b)| a != b)
+                .unwrap_or(expected.len().min(reply.len()));
+            let decode = |packet: &[u8]| match Message::deserialize(packet) {
+                Ok(message) => message.to_string(),
+                Err(e) => e.to_string(),
+            };
+            differences.push(format!(
+                "packet {} : reply differs at offset {:#x}\n  expected {}  received {}",
+                i + 1,
+                offset,
+                decode(expected),
+                decode(reply)
+            ));
+        }
+    }
+    differences
+}
+
+/// What the server must answer to a client packet
+#[derive(Clone, Copy, Debug)]
+enum Expected {
+    Nothing,
+    /// DHCPOFFER of ADDRESS for LEASE
+    Offer,
+    /// DHCPACK of ADDRESS for LEASE
+    Ack,
+    /// DHCPACK of a DHCPINFORM, without address nor lease
+    Parameters,
+    /// DHCPNAK, without address nor lease
+    Nak,
+}
+
+impl Expected {
+    fn check(self, replies: &[Vec<u8>]) -> Result<(), String> {
+        let (message_type, yiaddr, lease) = match self {
+            Expected::Nothing if replies.is_empty() => return Ok(()),
+            Expected::Nothing => return Err(format!("{} replies, none expected", replies.len())),
+            Expected::Offer => (MessageType::DHCPOFFER, ADDRESS, Some(LEASE)),
+            Expected::Ack => (MessageType::DHCPACK, ADDRESS, Some(LEASE)),
+            Expected::Parameters => (MessageType::DHCPACK, Ipv4Addr::UNSPECIFIED, None),
+            Expected::Nak => (MessageType::DHCPNAK, Ipv4Addr::UNSPECIFIED, None),
+        };
+        let reply = match replies {
+            [reply] => Message::deserialize(reply).map_err(|e| e.to_string())?,
+            _ => return Err(format!("{} replies, one expected", replies.len())),
+        };
+        let option = |code| reply.option(code).map(|option| option.data.clone());
+        let received = (
+            reply.message_type(),
+            reply.yiaddr,
+            option(Option::IPAddressLeaseTime),
+            option(Option::ServerIdentifier),
+        );
+        let expected = (
+            Some(message_type),
+            yiaddr,
+            lease.map(|lease| lease.to_be_bytes().to_vec()),
+            Some(SERVER_IDENTIFIER.octets().to_vec()),
+        );
+        if received == expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "{:?} expected, received (type, yiaddr, lease, server identifier) {:?}",
+                self, received
+            ))
+        }
+    }
+}
+
+/// Play one scenario for every client which has it, `expected` gives what
+/// the server answers to each client packet
+fn scenario(name: &str, expected: &[Expected]) {
+    let updating = std::env::var_os("GOLDEN_UPDATE").is_some();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut clients: Vec<PathBuf> = fs::read_dir(&root)
+        .expect("golden directory")
+        .map(|entry| entry.expect("golden entry").path())
+        .filter(|path| path.is_dir())
+        .collect();
+    clients.sort();
+    let mut failures = Vec::new();
+    let mut played = 0;
+    for client in clients {
+        let path = client.join(format!("{}.txt", name));
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let mut golden =
+            Golden::parse(&content).unwrap_or_else(|e| panic!("{} : {}", path.display(), e));
+        let received = play(&golden);
+        played += 1;
+        let mut checked = Vec::new();
+        if golden.exchanges.len() != expected.len() {
+            checked.push(format!(
+                "{} client packets, {} expected",
+                golden.exchanges.len(),
+                expected.len()
+            ));
+        }
+        for (i, (replies, expected)) in received.iter().zip(expected).enumerate() {
+            if let Err(e) = expected.check(replies) {
+                checked.push(format!("packet {} : {}", i + 1, e));
+            }
+        }
+        if !checked.is_empty() {
+            for problem in checked {
+                failures.push(format!("{} : {}", path.display(), problem));
+            }
+            continue;
+        }
+        if updating {
+            for (exchange, replies) in golden.exchanges.iter_mut().zip(received) {
+                exchange.replies = replies;
+            }
+            fs::write(&path, golden.to_text()).expect("golden file written");
+            continue;
+        }
+        for difference in compare(&golden, &received) {
+            failures.push(format!("{} : {}", path.display(), difference));
+        }
+    }
+    assert!(played > 0, "no golden file for {}", name);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn initial_lease() {
+    scenario("initial-lease", &[Offer, Ack]);
+}
+
+#[test]
+fn renew() {
+    scenario("renew", &[Offer, Ack, Ack]);
+}
+
+#[test]
+fn rebind() {
+    scenario("rebind", &[Offer, Ack, Ack]);
+}
+
+#[test]
+fn reboot() {
+    // Refused once the requested address is on another network
+    scenario("reboot", &[Offer, Ack, Ack, Nak]);
+}
+
+#[test]
+fn decline() {
+    // No reply to the DHCPDECLINE, nothing left to offer after it
+    scenario("decline", &[Offer, Ack, Nothing, Nothing]);
+}
+
+#[test]
+fn release() {
+    // No reply to the DHCPRELEASE nor to the renewal of the released lease
+    scenario("release", &[Offer, Ack, Nothing, Nothing, Offer]);
+}
+
+#[test]
+fn inform() {
+    scenario("inform", &[Parameters]);
+}
@@ -0,0 +1,44 @@
+//! The history of the clients is bounded, in events per client and in
+//! number of clients.
+
+use rdhcp::configuration::Configuration;
+use rdhcp::server::{Pool, HISTORY_CLIENTS};
+use std::net::Ipv4Addr;
+use std::thread;
+use std::time::Duration;
+
+const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 0, 0, 10);
+
+fn mac(i: usize) -> String {
+    format!("02:00:00:00:{:02x}:{:02x}", i >> 8, i & 0xff)
+}
+
+#[test]
+fn oldest_client_is_forgotten() {
+    let mut pool = Pool::new(Configuration::default());
+    pool.record("02:00:00:00:ff:ff", "bound", ADDRESS);
+    // The history is dated to the second
+    thread::sleep(Duration::from_millis(1100));
+    for i in 1..HISTORY_CLIENTS {
+        pool.record(&mac(i), "offered", ADDRESS);
+    }
+    assert_eq!(pool.history.len(), HISTORY_CLIENTS);
+
+    pool.record(&mac(HISTORY_CLIENTS), "offered", ADDRESS);
+    assert_eq!(pool.history.len(), HISTORY_CLIENTS);
+    assert!(!pool.history.contains_key("02:00:00:00:ff:ff"));
+
+    // A known client keeps its place and its events
+    pool.record(&mac(1), "bound", ADDRESS);
+    assert_eq!(pool.history.len(), HISTORY_CLIENTS);
+    assert_eq!(pool.history[&mac(1)].len(), 2);
+}
+
+#[test]
+fn events_per_client() {
+    let mut pool = Pool::new(Configuration::default());
+    for _ in 0..100 {
+        pool.record(&mac(1), "offered", ADDRESS);
+    }
+    assert_eq!(pool.history[&mac(1)].len(), 32);
+}
@@ -0,0 +1,148 @@
+//! Conflict detection : addresses answering a probe are skipped and marked
+//! as abandoned until their hold time is over, the probing time of a request
+//! is bounded, and only the echo replies to our own requests count.
+
+use rdhcp::configuration::{AddressRange, Configuration};
+use rdhcp::probe::{is_echo_reply, Prober};
+use rdhcp::server::{ErrorPool, Pool};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IN_USE: Ipv4Addr = Ipv4Addr::new(192, 0, 0, 1);
+const FREE: Ipv4Addr = Ipv4Addr::new(192, 0, 0, 2);
+
+/// Sees someone on `in_use` only, and keeps what it was asked
+struct MockProber {
+    in_use: Ipv4Addr,
+    probed: Arc<Mutex<Vec<(Ipv4Addr, Duration)>>>,
+}
+impl Prober for MockProber {
+    fn is_in_use(&self, addr: Ipv4Addr, timeout: Duration) -> bool {
+        self.probed.lock().unwrap().push((addr, timeout));
+        addr == self.in_use
+    }
+}
+
+#[test]
+fn conflicting_address_is_abandoned() {
+    let mut configuration = Configuration::default();
+    configuration.subnets[0].ranges = vec![AddressRange::new(IN_USE, FREE)];
+    configuration.conflict_detection.enabled = true;
+    configuration.conflict_detection.timeout = 200;
+    let probed = Arc::new(Mutex::new(Vec::new()));
+    let prober = MockProber {
+        in_use: IN_USE,
+        probed: probed.clone(),
+    };
+    let mut pool = Pool::with_prober(configuration, Box::new(prober));
+
+    // Whichever address is tried first, the one in use is never offered
+    let client = pool
+        .reserve_ip("02:00:00:00:00:01".to_string(), 0, &[])
+        .expect("the free address is offered");
+    assert_eq!(client.address(), FREE);
+    let exhausted = pool.reserve_ip("02:00:00:00:00:02".to_string(), 0, &[]);
+    assert!(matches!(exhausted, Err(ErrorPool::PoolExhausted)));
+    assert!(pool.abandoned.contains_key(&IN_USE));
+    assert!(probed
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(_, timeout)| *timeout == Duration::from_millis(200)));
+
+    // The abandoned address is not probed again
+    probed.lock().unwrap().clear();
+    let exhausted = pool.reserve_ip("02:00:00:00:00:03".to_string(), 0, &[]);
+    assert!(matches!(exhausted, Err(ErrorPool::PoolExhausted)));
+    assert!(probed.lock().unwrap().is_empty());
+}
+
+/// Everything is in use, and takes the whole timeout to answer
+struct SlowProber {
+    probed: Arc<Mutex<Vec<Duration>>>,
+}
+impl Prober for SlowProber {
+    fn is_in_use(&self, _addr: Ipv4Addr, timeout: Duration) -> bool {
+        self.probed.lock().unwrap().push(timeout);
+        thread::sleep(timeout);
+        true
+    }
+}
+
+#[test]
+fn probing_time_is_bounded() {
+    let mut configuration = Configuration::default();
+    configuration.conflict_detection.enabled = true;
+    configuration.conflict_detection.timeout = 100;
+    configuration.conflict_detection.budget = 250;
+    let probed = Arc::new(Mutex::new(Vec::new()));
+    let prober = SlowProber {
+        probed: probed.clone(),
+    };
+    let mut pool = Pool::with_prober(configuration, Box::new(prober));
+
+    let started = Instant::now();
+    let refused = pool.reserve_ip("02:00:00:00:00:01".to_string(), 0, &[]);
+    assert!(matches!(refused, Err(ErrorPool::ProbeBudgetExhausted)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    // The last probe only gets what is left of the budget
+    let probed = probed.lock().unwrap();
+    assert!(probed.len() >= 2);
+    assert_eq!(probed[0], Duration::from_millis(100));
+    assert!(probed.iter().sum::<Duration>() <= Duration::from_millis(250));
+    assert_eq!(pool.abandoned.len(), probed.len());
+}
+
+#[test]
+fn abandoned_address_is_reclaimed() {
+    let mut configuration = Configuration::default();
+    configuration.subnets[0].ranges = vec![AddressRange::new(IN_USE, IN_USE)];
+    configuration.conflict_detection.enabled = true;
+    configuration.conflict_detection.abandon_time = 0;
+    let probed = Arc::new(Mutex::new(Vec::new()));
+    let prober = MockProber {
+        in_use: IN_USE,
+        probed: probed.clone(),
+    };
+    let mut pool = Pool::with_prober(configuration, Box::new(prober));
+
+    for mac in ["02:00:00:00:00:01", "02:00:00:00:00:02"] {
+        let exhausted = pool.reserve_ip(mac.to_string(), 0, &[]);
+        assert!(matches!(exhausted, Err(ErrorPool::PoolExhausted)));
+    }
+    // Probed again once its hold time is over
+    assert_eq!(probed.lock().unwrap().len(), 2);
+    assert!(pool.abandoned.contains_key(&IN_USE));
+}
+
+/// Echo reply of `identifier` and `sequence`, with an IP header if `raw`
+fn echo_reply(kind: u8, identifier: u16, sequence: u16, raw: bool) -> Vec<u8> {
+    let mut packet = Vec::new();
+    if raw {
+        packet.extend_from_slice(&[0x45, 0, 0, 33, 0, 0, 0, 0, 64, 1, 0, 0]);
+        packet.extend_from_slice(&FREE.octets());
+        packet.extend_from_slice(&[192, 0, 0, 254]);
+    }
+    packet.extend_from_slice(&[kind, 0, 0, 0]);
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"rdhcp");
+    packet
+}
+
+#[test]
+fn only_our_echo_replies_count() {
+    for raw in [false, true] {
+        assert!(is_echo_reply(&echo_reply(0, 1234, 7, raw), 1234, 7));
+        // Reply to a concurrent ping, or to one of our previous probes
+        assert!(!is_echo_reply(&echo_reply(0, 4321, 7, raw), 1234, 7));
+        assert!(!is_echo_reply(&echo_reply(0, 1234, 6, raw), 1234, 7));
+        // Echo request, or destination unreachable
+        assert!(!is_echo_reply(&echo_reply(8, 1234, 7, raw), 1234, 7));
+        assert!(!is_echo_reply(&echo_reply(3, 1234, 7, raw), 1234, 7));
+    }
+    assert!(!is_echo_reply(&[0, 0, 0, 0], 0, 0));
+    assert!(!is_echo_reply(&[], 0, 0));
+}
@@ -0,0 +1,183 @@
+//! TFTP transfers from a temporary root : octet and netascii modes, the
+//! blksize and tsize options, retransmissions, and files outside of the root
+//! refused even through a symbolic link.
+
+use rdhcp::configuration::Tftp;
+use rdhcp::tftp::{resolve, TftpServer};
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONTENT: &[u8] = b"#!ipxe\nchain boot.efi\rend\n";
+
+/// Root with a file, and a link to a file outside of it
+fn root(name: &str) -> PathBuf {
+    let base = std::env::temp_dir().join(format!("rdhcp-tftp-{}-{}", name, std::process::id()));
+    let root = base.join("root");
+    let _ = fs::remove_dir_all(&base);
+    fs::create_dir_all(&root).expect("temporary root");
+    fs::write(root.join("boot.ipxe"), CONTENT).expect("boot file");
+    fs::write(base.join("secret"), b"secret").expect("file outside of the root");
+    symlink(base.join("secret"), root.join("escape")).expect("symbolic link");
+    symlink("boot.ipxe", root.join("inside")).expect("symbolic link");
+    root
+}
+
+fn server(root: &Path) -> SocketAddr {
+    let server = TftpServer::new(&Tftp {
+        root: root.display().to_string(),
+        address: "127.0.0.1:0".parse().unwrap(),
+    })
+    .expect("TFTP server");
+    let address = server.local_addr().expect("TFTP address");
+    thread::spawn(move || server.on_recv());
+    address
+}
+
+/// Read request packet
+fn request(filename: &str, mode: &str, options: &[(&str, &str)]) -> Vec<u8> {
+    let mut request = vec![0, 1];
+    for field in [filename, mode]
+        .into_iter()
+        .chain(options.iter().flat_map(|(name, value)| [*name, *value]))
+    {
+        request.extend_from_slice(field.as_bytes());
+        request.push(0);
+    }
+    request
+}
+
+/// Read a file, the data received or the error message is returned
+fn read(server: SocketAddr, filename: &str, mode: &str) -> Result<Vec<u8>, String> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket
+        .send_to(&request(filename, mode, &[]), server)
+        .unwrap();
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buffer).expect("TFTP answer");
+        let packet = &buffer[..len];
+        match packet[1] {
+            3 => {
+                data.extend_from_slice(&packet[4..]);
+                socket.send_to(&[0, 4, packet[2], packet[3]], peer).unwrap();
+                if len - 4 < 512 {
+                    return Ok(data);
+                }
+            }
+            5 => return Err(String::from_utf8_lossy(&packet[4..len - 1]).to_string()),
+            opcode => panic!("unexpected opcode {}", opcode),
+        }
+    }
+}
+
+#[test]
+fn modes() {
+    let root = root("modes");
+    let server = server(&root);
+    assert_eq!(read(server, "boot.ipxe", "octet"), Ok(CONTENT.to_vec()));
+    assert_eq!(
+        read(server, "/boot.ipxe", "NETASCII"),
+        Ok(b"#!ipxe\r\nchain boot.efi\r\0end\r\n".to_vec())
+    );
+    assert_eq!(
+        read(server, "boot.ipxe", "mail"),
+        Err("unsupported mode mail".to_string())
+    );
+}
+
+#[test]
+fn outside_of_the_root() {
+    let root = root("outside");
+    let server = server(&root);
+    let not_found = Err("file not found".to_string());
+    assert_eq!(read(server, "escape", "octet"), not_found);
+    assert_eq!(read(server, "../secret", "octet"), not_found);
+    assert_eq!(read(server, "inside", "octet"), Ok(CONTENT.to_vec()));
+
+    assert_eq!(resolve(&root, "escape"), None);
+    assert_eq!(
+        resolve(&root, "inside"),
+        Some(root.canonicalize().unwrap().join("boot.ipxe"))
+    );
+}
+
+#[test]
+fn block_size_and_transfer_size() {
+    let root = root("options");
+    // Exact multiple of the block size, the last block is empty
+    let content: Vec<u8> = (0..1024u32).map(|i| (i % 251) as u8).collect();
+    fs::write(root.join("kernel"), &content).unwrap();
+    let server = server(&root);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    // Unknown options make the request longer than 512 bytes, they are ignored
+    let padding = "x".repeat(600);
+    let options = [("blksize", "256"), ("tsize", "0"), ("padding", &padding)];
+    socket
+        .send_to(&request("kernel", "octet", &options), server)
+        .unwrap();
+    let mut buffer = [0u8; 1024];
+    let (len, peer) = socket.recv_from(&mut buffer).expect("OACK");
+    assert_eq!(&buffer[..len], b"\x00\x06blksize\x00256\x00tsize\x001024\x00");
+    socket.send_to(&[0, 4, 0, 0], peer).unwrap();
+    let mut data = Vec::new();
+    let mut sizes = Vec::new();
+    for block in 1..=5u16 {
+        let (len, _) = socket.recv_from(&mut buffer).expect("DATA");
+        assert_eq!(buffer[..4], [0, 3, 0, block as u8]);
+        data.extend_from_slice(&buffer[4..len]);
+        sizes.push(len - 4);
+        socket.send_to(&[0, 4, 0, block as u8], peer).unwrap();
+    }
+    assert_eq!(sizes, [256, 256, 256, 256, 0]);
+    assert_eq!(data, content);
+    // Nothing follows the empty block
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    assert!(socket.recv_from(&mut buffer).is_err());
+}
+
+#[test]
+fn stray_acks_do_not_delay_retransmission() {
+    let root = root("retransmission");
+    let server = server(&root);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket
+        .send_to(&request("boot.ipxe", "octet", &[]), server)
+        .unwrap();
+    let mut buffer = [0u8; 1024];
+    let (_, peer) = socket.recv_from(&mut buffer).expect("DATA");
+    assert_eq!(buffer[..4], [0, 3, 0, 1]);
+    // Duplicated ACKs of block 0 until the first block is sent again
+    let start = Instant::now();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    loop {
+        socket.send_to(&[0, 4, 0, 0], peer).unwrap();
+        if socket.recv_from(&mut buffer).is_ok() {
+            assert_eq!(buffer[..4], [0, 3, 0, 1]);
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(3),
+            "no retransmission"
+        );
+    }
+    assert!(start.elapsed() < Duration::from_millis(1500));
+    socket.send_to(&[0, 4, 0, 1], peer).unwrap();
+}
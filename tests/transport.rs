@@ -0,0 +1,106 @@
+//! The server loop keeps going when receiving fails, and stops when the
+//! transport is closed. Replies carry the configured server identifier.
+
+use dhcp_proto::options::Option;
+use dhcp_proto::{Message, MessageType, MAGIC_COOKIE};
+use rdhcp::configuration::Configuration;
+use rdhcp::server::DhcpServer;
+use rdhcp::transport::Transport;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+
+const SERVER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 0, 0, 254), 67));
+const CLIENT: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 0, 0, 50), 68));
+// ENOBUFS on linux
+const NO_BUFFER_SPACE: i32 = 105;
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// Gives the results of `recv_from` in order, then `NotConnected`
+struct ScriptedTransport {
+    received: Mutex<VecDeque<io::Result<Datagram>>>,
+    sent: Arc<Mutex<Vec<Datagram>>>,
+}
+impl Transport for ScriptedTransport {
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (packet, src) = self
+            .received
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Err(io::ErrorKind::NotConnected.into()))?;
+        buffer[..packet.len()].copy_from_slice(&packet);
+        Ok((packet.len(), src))
+    }
+    fn send_to(&self, buffer: &[u8], dest: SocketAddr) -> io::Result<usize> {
+        self.sent.lock().unwrap().push((buffer.to_vec(), dest));
+        Ok(buffer.len())
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SERVER)
+    }
+}
+
+/// DHCPINFORM from a client of the default subnet
+fn inform() -> Vec<u8> {
+    let mut packet = vec![0u8; 236];
+    packet[..4].copy_from_slice(&[1, 1, 6, 0]);
+    packet[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+    packet[12..16].copy_from_slice(&[192, 0, 0, 50]); // ciaddr
+    packet[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]); // chaddr
+    packet.extend_from_slice(&MAGIC_COOKIE);
+    packet.extend_from_slice(&[
+        Option::DHCPMessageType as u8,
+        1,
+        MessageType::DHCPINFORM as u8,
+        255,
+    ]);
+    packet
+}
+
+/// Replies sent by the server to the packets received
+fn replies(received: VecDeque<io::Result<Datagram>>, configuration: Configuration) -> Vec<Message> {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let transport = ScriptedTransport {
+        received: Mutex::new(received),
+        sent: sent.clone(),
+    };
+    // Returns once the script is over
+    DhcpServer::with_transport(Box::new(transport)).on_recv(configuration);
+    let sent = sent.lock().unwrap();
+    sent.iter()
+        .map(|(reply, _)| Message::deserialize(reply).expect("valid reply"))
+        .collect()
+}
+
+#[test]
+fn receive_errors_are_not_fatal() {
+    let received = VecDeque::from([
+        Err(io::ErrorKind::ConnectionRefused.into()),
+        Err(io::Error::from_raw_os_error(NO_BUFFER_SPACE)),
+        Ok((inform(), CLIENT)),
+    ]);
+    let replies = replies(received, Configuration::default());
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].message_type(), Some(MessageType::DHCPACK));
+    assert_eq!(replies[0].xid, 0x1234_5678);
+    let identifier = replies[0]
+        .option(Option::ServerIdentifier)
+        .expect("server identifier");
+    assert_eq!(identifier.data, [192, 0, 0, 254]);
+}
+
+#[test]
+fn configured_server_identifier() {
+    let configuration = Configuration {
+        server_identifier: Some(Ipv4Addr::new(192, 0, 0, 253)),
+        ..Configuration::default()
+    };
+    let replies = replies(VecDeque::from([Ok((inform(), CLIENT))]), configuration);
+    let identifier = replies[0]
+        .option(Option::ServerIdentifier)
+        .expect("server identifier");
+    assert_eq!(identifier.data, [192, 0, 0, 253]);
+}